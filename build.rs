@@ -0,0 +1,8 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protoc is vendored so building needs nothing beyond cargo.
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::configure()
+        .build_client(false)
+        .compile(&["proto/random.proto"], &["proto"])?;
+    Ok(())
+}
@@ -0,0 +1,622 @@
+//! The settings the server runs with, read from TOML files and `RNG_`
+//! environment variables over the defaults, and from command-line overrides.
+
+use super::*;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub(crate) address: Option<SocketAddr>,
+    pub(crate) host: IpAddr,
+    pub(crate) port: u16,
+    /// Serves on this socket instead of TCP, or as well as TCP when `address`
+    /// is also given.
+    pub(crate) unix_socket: Option<PathBuf>,
+    pub(crate) tls: Option<TlsConfig>,
+    /// Where the gRPC interface listens, over cleartext HTTP/2; none by
+    /// default.
+    pub(crate) grpc_address: Option<SocketAddr>,
+    /// Where `/admin` is served, instead of alongside the other routes,
+    /// where it is only served when `api_keys` are configured.
+    pub(crate) admin_address: Option<SocketAddr>,
+    /// Accept loops serving the TCP address, each on its own listener bound
+    /// with `SO_REUSEPORT` so the kernel spreads connections between them.
+    pub(crate) workers: usize,
+    pub(crate) dual_stack: bool,
+    pub(crate) envelope: bool,
+    pub(crate) max_count: usize,
+    /// Estimated memory a `count` batch may hold, so batches of wide vectors
+    /// are capped lower than batches of scalars.
+    pub(crate) max_batch_bytes: usize,
+    pub(crate) max_histogram_count: usize,
+    /// Most histogram `bins`, each of which the response carries.
+    pub(crate) max_bins: usize,
+    pub(crate) rng_mode: RngMode,
+    pub(crate) entropy_source: EntropySource,
+    pub(crate) param_policy: ParamPolicy,
+    pub(crate) seed: Option<u64>,
+    pub(crate) write_timeout_secs: Option<u64>,
+    pub(crate) fixed_point: bool,
+    pub(crate) log_sample_rate: f64,
+    pub(crate) log_format: LogFormat,
+    /// Level of this service's own log lines when `RUST_LOG` is unset, such
+    /// as `info` or `debug`.
+    pub(crate) log_level: Option<String>,
+    /// Response formats clients may ask for, by their `?format=` names; all
+    /// of them when unset.
+    pub(crate) formats: Option<Vec<String>>,
+    pub(crate) max_string_len: usize,
+    /// Longest `/bytes` request, in bytes before encoding.
+    pub(crate) max_bytes_len: usize,
+    pub(crate) max_burnin: usize,
+    /// Largest hypergeometric `population`; sampling takes up to half of it
+    /// in steps per value.
+    pub(crate) max_hypergeometric_population: u64,
+    pub(crate) chaos_enabled: bool,
+    pub(crate) max_delay_ms: u64,
+    pub(crate) max_body_bytes: usize,
+    pub(crate) body_limits: BTreeMap<String, usize>,
+    pub(crate) max_streams: usize,
+    /// Largest absolute value accepted for any float parameter, keeping
+    /// results within the range where f64 still represents integers exactly.
+    pub(crate) max_param_magnitude: f64,
+    pub(crate) rate_limit: Option<RateLimit>,
+    pub(crate) distribution_rate_limits: BTreeMap<String, RateLimit>,
+    /// Responses shorter than this are sent uncompressed whatever the client
+    /// accepts, since the encoding overhead outweighs the savings.
+    pub(crate) compression_min_bytes: usize,
+    /// In-flight request count above which responses carry `Retry-After`,
+    /// asking well-behaved clients to slow down before the server saturates.
+    pub(crate) queue_high_water: Option<usize>,
+    /// NDJSON file recording every generated value. When a write would take
+    /// it past `audit_log_max_bytes` it is renamed with a `.1` suffix,
+    /// replacing the previous one, and a new file is started.
+    pub(crate) audit_log_path: Option<PathBuf>,
+    pub(crate) audit_log_max_bytes: u64,
+    pub(crate) slow_request_threshold_ms: Option<u64>,
+    /// Time a handler has to produce a response before the request is
+    /// answered with 408 instead; unlimited when unset.
+    pub(crate) request_timeout_ms: Option<u64>,
+    /// Headers added to every response, replacing any the handler set.
+    pub(crate) response_headers: BTreeMap<String, String>,
+    pub(crate) load_shedding: Option<LoadShedding>,
+    /// Keys accepted in `X-Api-Key` or `Authorization: Bearer`, along with
+    /// any in the comma-separated `API_KEYS` environment variable. With none,
+    /// requests are not authenticated.
+    pub(crate) api_keys: Vec<String>,
+    /// File holding the key, as written by `run key`, that `/random`
+    /// responses are signed with: `X-Signature` is the hex HMAC-SHA256 of the
+    /// body keyed by the file's contents without surrounding whitespace.
+    pub(crate) signing_key_path: Option<PathBuf>,
+    pub(crate) cors: Option<CorsConfig>,
+}
+
+/// Token bucket refilled at `requests_per_second` and holding up to `burst`
+/// requests, kept separately for each client IP, or with `key_by = "api_key"`
+/// for each API key, falling back to the IP for requests without a valid one.
+#[derive(Clone, Copy, Deserialize)]
+pub(crate) struct RateLimit {
+    pub(crate) requests_per_second: f64,
+    pub(crate) burst: f64,
+    #[serde(default)]
+    pub(crate) key_by: RateLimitKey,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RateLimitKey {
+    #[default]
+    Ip,
+    ApiKey,
+}
+
+/// System-wide CPU and memory use, in percent, above which requests other
+/// than health checks are rejected with 503 until the next check finds the
+/// load back under both.
+#[derive(Clone, Copy, Deserialize)]
+pub(crate) struct LoadShedding {
+    pub(crate) max_cpu_percent: Option<f32>,
+    pub(crate) max_memory_percent: Option<f32>,
+    #[serde(default = "default_check_interval_ms")]
+    pub(crate) check_interval_ms: u64,
+}
+
+fn default_check_interval_ms() -> u64 {
+    1000
+}
+
+/// Cross-origin access for browsers. Preflight `OPTIONS` requests from an
+/// allowed origin are answered without authentication, and every other
+/// response to one says the origin may read it. `"*"` allows any origin.
+#[derive(Deserialize)]
+#[serde(default)]
+pub(crate) struct CorsConfig {
+    pub(crate) allowed_origins: Vec<String>,
+    pub(crate) allowed_methods: Vec<String>,
+    pub(crate) allowed_headers: Vec<String>,
+    pub(crate) max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: ["GET", "POST", "DELETE"].map(String::from).to_vec(),
+            allowed_headers: ["content-type", "authorization", "x-api-key", "x-request-id"]
+                .map(String::from)
+                .to_vec(),
+            max_age_secs: 600,
+        }
+    }
+}
+
+/// Response headers a cross-origin page may read besides the safelisted ones.
+const CORS_EXPOSED_HEADERS: &str = "x-request-id, x-seed, x-signature, x-queue-depth, retry-after";
+
+impl CorsConfig {
+    /// The request's `Origin` when it is allowed.
+    pub(crate) fn origin<'a>(&self, headers: &'a HeaderMap) -> Option<&'a HeaderValue> {
+        let origin = headers.get("origin")?;
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || origin == allowed.as_str())
+            .then_some(origin)
+    }
+
+    pub(crate) fn is_preflight(&self, req: &Request<Body>) -> bool {
+        req.method() == Method::OPTIONS
+            && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+            && self.origin(req.headers()).is_some()
+    }
+
+    pub(crate) fn preflight(&self) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(
+                ACCESS_CONTROL_ALLOW_METHODS,
+                self.allowed_methods.join(", "),
+            )
+            .header(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                self.allowed_headers.join(", "),
+            )
+            .header(ACCESS_CONTROL_MAX_AGE, self.max_age_secs)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// Marks `headers` as readable by `origin`.
+    pub(crate) fn allow(&self, origin: HeaderValue, headers: &mut HeaderMap) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(
+            ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(CORS_EXPOSED_HEADERS),
+        );
+        headers.append(VARY, HeaderValue::from_static("origin"));
+    }
+}
+
+/// PEM files for serving HTTPS on the TCP listener. With `client_ca` set,
+/// clients must present a certificate signed by that CA.
+#[derive(Deserialize)]
+pub(crate) struct TlsConfig {
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+    pub(crate) client_ca: Option<PathBuf>,
+    #[serde(default)]
+    pub(crate) min_version: TlsVersion,
+    /// Cipher suite names as rustls spells them, such as
+    /// `TLS13_AES_256_GCM_SHA384`; all of rustls' suites when unset.
+    pub(crate) cipher_suites: Option<Vec<String>>,
+}
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+pub(crate) enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// How generators are shared between requests.
+///
+/// `thread_local` uses the OS-seeded `thread_rng` and is not reproducible.
+/// `shared_seeded` draws every value from one generator seeded with `seed`,
+/// so the whole response sequence replays exactly, but all workers contend
+/// on a single lock. `per_thread_seeded` gives each worker thread its own
+/// generator derived from `seed` and the order the thread first generated;
+/// each per-thread stream is reproducible, but which requests land on which
+/// thread is not, so only single-threaded replays are exact.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RngMode {
+    ThreadLocal,
+    SharedSeeded,
+    PerThreadSeeded,
+}
+
+/// How access log lines are written: `text` through the logger, or `json`,
+/// one object per line on stderr for log aggregation.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Which generator draws values, overridable per request with
+/// `?entropy_source=`, `?rng=` or an `rng` field in a `/random` body.
+///
+/// `thread_rng` is the default and the fastest; it is a CSPRNG reseeded from
+/// the OS, and honours `rng_mode`. `os_rng` reads every value from the
+/// operating system, which is the strongest choice for keys and tokens but
+/// costs a syscall per draw. `chacha` runs ChaCha20 on each worker thread,
+/// seeded like `per_thread_seeded`, for cryptographic quality from a
+/// reproducible seed at a few times the cost of `thread_rng`. `pcg` is a
+/// 64-bit permuted congruential generator seeded the same way: the fastest
+/// source, for simulations, but not for anything an adversary could predict.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum EntropySource {
+    ThreadRng,
+    OsRng,
+    Chacha,
+    Pcg,
+}
+
+impl FromStr for EntropySource {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Self, Error> {
+        match source {
+            "thread_rng" => Ok(EntropySource::ThreadRng),
+            "os_rng" => Ok(EntropySource::OsRng),
+            "chacha" => Ok(EntropySource::Chacha),
+            "pcg" => Ok(EntropySource::Pcg),
+            _ => Err(format_err!("unsupported entropy source {}", source)),
+        }
+    }
+}
+
+/// What happens to a distribution parameter outside its valid range.
+///
+/// `reject` answers 422. `clamp` moves it to the nearest valid value and logs
+/// a warning; parameters with no nearest valid value, such as an empty range
+/// or a non-positive `alpha`, are still rejected.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ParamPolicy {
+    Reject,
+    Clamp,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            address: None,
+            host: [127, 0, 0, 1].into(),
+            port: 8080,
+            unix_socket: None,
+            grpc_address: None,
+            admin_address: None,
+            workers: 1,
+            tls: None,
+            dual_stack: true,
+            envelope: true,
+            max_count: 10_000,
+            max_batch_bytes: 16 * 1024 * 1024,
+            max_histogram_count: 10_000_000,
+            max_bins: 1_000,
+            rng_mode: RngMode::ThreadLocal,
+            entropy_source: EntropySource::ThreadRng,
+            param_policy: ParamPolicy::Reject,
+            seed: None,
+            write_timeout_secs: None,
+            fixed_point: false,
+            log_sample_rate: 1.0,
+            log_format: LogFormat::Text,
+            log_level: None,
+            formats: None,
+            max_string_len: 1024,
+            max_bytes_len: 1024,
+            max_burnin: 1_000_000,
+            max_hypergeometric_population: 1_000_000,
+            chaos_enabled: false,
+            max_delay_ms: 10_000,
+            max_body_bytes: 64 * 1024,
+            body_limits: BTreeMap::new(),
+            max_streams: 1024,
+            max_param_magnitude: 1e15,
+            rate_limit: None,
+            distribution_rate_limits: BTreeMap::new(),
+            compression_min_bytes: 1024,
+            queue_high_water: None,
+            audit_log_path: None,
+            audit_log_max_bytes: 100 * 1024 * 1024,
+            slow_request_threshold_ms: None,
+            request_timeout_ms: None,
+            response_headers: BTreeMap::new(),
+            load_shedding: None,
+            api_keys: Vec::new(),
+            signing_key_path: None,
+            cors: None,
+        }
+    }
+}
+
+pub(crate) fn read_config(path: &str) -> io::Result<toml::Value> {
+    File::open(path)
+        .and_then(|mut file| {
+            let mut buffer = String::new();
+            file.read_to_string(&mut buffer)?;
+            Ok(buffer)
+        })
+        .and_then(|buffer| toml::from_str::<toml::Value>(&buffer).map_err(io::Error::other))
+}
+
+fn merge_config(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_config(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+pub(crate) fn response_headers(headers: &BTreeMap<String, String>) -> Result<HeaderMap, Error> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format_err!("invalid header name {:?}", name))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| format_err!("invalid value for header {}: {:?}", name, value))?;
+        map.insert(name, value);
+    }
+    Ok(map)
+}
+
+/// Settings from `RNG_` environment variables: `RNG_MAX_COUNT=100` sets
+/// `max_count`, and `__` separates table names, as in `RNG_TLS__CERT`.
+/// Values are read as TOML, falling back to a string.
+fn env_config(vars: impl Iterator<Item = (String, String)>) -> toml::Value {
+    let mut overrides = toml::Value::Table(Default::default());
+    for (name, raw) in vars {
+        let path = match name.strip_prefix("RNG_") {
+            Some(path) if !path.is_empty() => path.to_lowercase(),
+            _ => continue,
+        };
+        let value = toml::from_str::<toml::Value>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut parsed| parsed.as_table_mut()?.remove("value"))
+            .unwrap_or(toml::Value::String(raw));
+        let value = path.rsplit("__").fold(value, |value, key| {
+            let mut table = toml::value::Table::new();
+            table.insert(key.to_owned(), value);
+            toml::Value::Table(table)
+        });
+        merge_config(&mut overrides, value);
+    }
+    overrides
+}
+
+/// Merges the config files, then `RNG_` environment variables, over the
+/// defaults. A missing file is skipped, and any other error falls back to the
+/// defaults with a warning unless `strict`.
+pub fn load_config<'a>(
+    paths: impl Iterator<Item = &'a str>,
+    strict: bool,
+) -> Result<Config, Error> {
+    let mut merged = toml::Value::Table(Default::default());
+    for path in paths {
+        match read_config(path) {
+            Ok(value) => {
+                debug!("Merging config file: {}", path);
+                merge_config(&mut merged, value);
+            }
+            Err(ref err) if strict && err.kind() != io::ErrorKind::NotFound => {
+                bail!("cannot read config file {}: {}", path, err)
+            }
+            Err(err) => warn!("Cannot read config file {}: {}", path, err),
+        }
+    }
+    merge_config(&mut merged, env_config(env::vars()));
+    match merged.try_into::<Config>() {
+        Ok(config) => Ok(config),
+        Err(err) if strict => bail!("cannot read config: {}", err),
+        Err(err) => {
+            warn!("Cannot read config: {}, using defaults", err);
+            Ok(Config::default())
+        }
+    }
+}
+
+/// Checks the settings that are only parsed once the server starts.
+pub(crate) fn check_config(config: &Config) -> Result<(), Error> {
+    response_headers(&config.response_headers)
+        .map_err(|err| format_err!("invalid response_headers: {}", err))?;
+    if let Some(ref cors) = config.cors {
+        for value in cors.allowed_methods.iter().chain(&cors.allowed_headers) {
+            HeaderValue::from_str(value)
+                .map_err(|_| format_err!("invalid cors entry {:?}", value))?;
+        }
+    }
+    if let Some(ref level) = config.log_level {
+        level
+            .parse::<LevelFilter>()
+            .map_err(|_| format_err!("invalid log_level {:?}", level))?;
+    }
+    for format in config.formats.iter().flatten() {
+        format.parse::<Format>()?;
+    }
+    if let Some(ref tls) = config.tls {
+        tls_config(tls)?;
+    }
+    if config.workers == 0 {
+        bail!("workers must be at least 1");
+    }
+    if config.max_bins == 0 {
+        bail!("max_bins must be at least 1");
+    }
+    let limits = config.rate_limit.iter();
+    for limit in limits.chain(config.distribution_rate_limits.values()) {
+        if !(limit.requests_per_second > 0.0 && limit.burst >= 1.0) {
+            bail!("rate limits need a positive requests_per_second and a burst of at least 1");
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn config_from_args(matches: &ArgMatches) -> Config {
+    let strict = matches.is_present("strict-config")
+        || env::var("STRICT_CONFIG").is_ok_and(|value| value == "1" || value == "true");
+    load_args_config(matches, strict).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    })
+}
+
+/// Loads the config files `matches` names and applies its overrides.
+pub(crate) fn load_args_config(matches: &ArgMatches, strict: bool) -> Result<Config, Error> {
+    let res = match matches.values_of("config") {
+        Some(paths) => load_config(paths, strict),
+        None => load_config(std::iter::once("microservice.toml"), strict),
+    };
+    res.and_then(|mut config| {
+        check_config(&config)?;
+        if let Some(seed) = matches.value_of("seed") {
+            let seed = seed
+                .parse()
+                .map_err(|err| format_err!("invalid seed {:?}: {}", seed, err))?;
+            config.seed = Some(seed);
+            if config.rng_mode == RngMode::ThreadLocal {
+                config.rng_mode = RngMode::SharedSeeded;
+            }
+        }
+        // `--address` overrides `ADDRESS`, which overrides `address`.
+        let address = matches
+            .value_of("address")
+            .map(|addr| ("--address", addr.to_owned()))
+            .or_else(|| Some(("ADDRESS", env::var("ADDRESS").ok()?)));
+        if let Some((name, addr)) = address {
+            let addr = addr
+                .parse()
+                .map_err(|err| format_err!("invalid {} {:?}: {}", name, addr, err))?;
+            config.address = Some(addr);
+        }
+        if let Ok(port) = env::var("PORT") {
+            config.port = port
+                .parse()
+                .map_err(|err| format_err!("invalid PORT {:?}: {}", port, err))?;
+        }
+        if let Some(path) = matches.value_of("unix-socket") {
+            config.unix_socket = Some(path.into());
+        }
+        if let Some(workers) = matches.value_of("workers") {
+            config.workers = workers
+                .parse()
+                .ok()
+                .filter(|&workers| workers > 0)
+                .ok_or_else(|| {
+                    format_err!("workers must be a positive integer, not {:?}", workers)
+                })?;
+        }
+        if let (Some(cert), Some(key)) = (matches.value_of("tls-cert"), matches.value_of("tls-key"))
+        {
+            let tls = config.tls.get_or_insert_with(|| TlsConfig {
+                cert: PathBuf::new(),
+                key: PathBuf::new(),
+                client_ca: None,
+                min_version: TlsVersion::default(),
+                cipher_suites: None,
+            });
+            tls.cert = cert.into();
+            tls.key = key.into();
+        }
+        if let (Some(tls), Some(ca)) = (config.tls.as_mut(), matches.value_of("tls-client-ca")) {
+            tls.client_ca = Some(ca.into());
+        }
+        match config.log_level {
+            Some(ref level) if env::var_os("RUST_LOG").is_none() => {
+                log::set_max_level(level.parse().unwrap());
+            }
+            _ => {}
+        }
+        if let Ok(keys) = env::var("API_KEYS") {
+            let keys = keys.split(',').map(str::trim).filter(|key| !key.is_empty());
+            config.api_keys.extend(keys.map(str::to_owned));
+        }
+        Ok(config)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn toml(text: &str) -> toml::Value {
+        toml::from_str(text).unwrap()
+    }
+
+    /// `run` matches for `args`, reading no config file.
+    fn run_args(args: &[&str]) -> ArgMatches<'static> {
+        let config = ["run", "--config", "/nonexistent/microservice.toml"];
+        App::new("run")
+            .arg(Arg::with_name("address").long("address").takes_value(true))
+            .arg(Arg::with_name("config").long("config").takes_value(true))
+            .get_matches_from(config.iter().chain(args).copied())
+    }
+
+    #[test]
+    fn later_configs_override_earlier_ones_key_by_key() {
+        let mut merged = toml("max_count = 1\nseed = 2\n[tls]\ncert = \"a.pem\"\nkey = \"a.key\"");
+        merge_config(&mut merged, toml("max_count = 3\n[tls]\ncert = \"b.pem\""));
+        let expected = toml("max_count = 3\nseed = 2\n[tls]\ncert = \"b.pem\"\nkey = \"a.key\"");
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn environment_variables_override_config_files() {
+        let vars = [
+            ("RNG_MAX_COUNT", "100"),
+            ("RNG_TLS__CERT", "/etc/rng/cert.pem"),
+            ("RNG_LOG_LEVEL", "debug"),
+            ("RNG_", "ignored"),
+            ("MAX_COUNT", "5"),
+        ];
+        let vars = vars
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()));
+        let env = env_config(vars);
+        let expected =
+            toml("max_count = 100\nlog_level = \"debug\"\n[tls]\ncert = \"/etc/rng/cert.pem\"");
+        assert_eq!(env, expected);
+        let mut merged = toml("max_count = 10\n[tls]\ncert = \"file.pem\"\nkey = \"file.key\"");
+        merge_config(&mut merged, env);
+        let config = merged.try_into::<Config>().unwrap();
+        assert_eq!(config.max_count, 100);
+        assert_eq!(config.log_level.as_deref(), Some("debug"));
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.cert, Path::new("/etc/rng/cert.pem"));
+        assert_eq!(tls.key, Path::new("file.key"));
+    }
+
+    #[test]
+    fn address_flag_is_parsed_or_rejected() {
+        let config = load_args_config(&run_args(&["--address", "[::]:8080"]), true).unwrap();
+        let expected = SocketAddr::from((Ipv6Addr::UNSPECIFIED, 8080));
+        assert_eq!(config.address, Some(expected));
+        for addr in &["localhost:8080", "[::]", "::1:8080", "[::1:8080"] {
+            let res = load_args_config(&run_args(&["--address", addr]), true);
+            assert!(res.is_err(), "{} accepted", addr);
+        }
+    }
+}
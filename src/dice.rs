@@ -0,0 +1,188 @@
+//! Dice notation: `+` or `-` separated terms that are either `NdS`, `N` dice
+//! of `S` sides with `N` defaulting to one, or integer modifiers, as in
+//! `3d6+2` or `d20-1d4`.
+
+use failure::Error;
+use rand::Rng;
+use serde_derive::Serialize;
+use std::str::FromStr;
+
+/// Most dice a single expression may roll.
+pub const MAX_DICE: u32 = 1_000;
+
+enum Term {
+    Dice { count: u32, sides: u32 },
+    Modifier(u32),
+}
+
+pub struct Dice {
+    /// Each term, with whether it is subtracted.
+    terms: Vec<(bool, Term)>,
+}
+
+#[derive(Serialize)]
+pub struct DiceRoll {
+    pub dice: String,
+    pub rolls: Vec<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Roll {
+    pub dice: Vec<DiceRoll>,
+    pub modifier: i64,
+    pub total: i64,
+}
+
+fn number(digits: &str, what: &str) -> Result<u32, Error> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        bail!("{} must be a positive integer, got {:?}", what, digits);
+    }
+    digits
+        .parse()
+        .map_err(|_| format_err!("{} {} is too large", what, digits))
+}
+
+fn parse_term(term: &str) -> Result<Term, Error> {
+    if term.is_empty() {
+        bail!("expected a term such as 2d6 or 3");
+    }
+    match term.split_once('d') {
+        Some((count, sides)) => {
+            let count = if count.is_empty() {
+                1
+            } else {
+                number(count, "dice count")?
+            };
+            let sides = number(sides, "number of sides")?;
+            if count == 0 || sides == 0 {
+                bail!("{} needs at least one die of at least one side", term);
+            }
+            Ok(Term::Dice { count, sides })
+        }
+        None => Ok(Term::Modifier(number(term, "modifier")?)),
+    }
+}
+
+impl FromStr for Dice {
+    type Err = Error;
+
+    fn from_str(notation: &str) -> Result<Self, Error> {
+        let notation = notation.trim().to_ascii_lowercase();
+        let (mut negative, mut rest) = match notation.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, notation.strip_prefix('+').unwrap_or(&notation)),
+        };
+        let mut terms = Vec::new();
+        let mut dice = 0u32;
+        loop {
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = parse_term(rest[..end].trim())?;
+            if let Term::Dice { count, .. } = term {
+                dice = dice.saturating_add(count);
+                if dice > MAX_DICE {
+                    bail!("rolls more than {} dice", MAX_DICE);
+                }
+            }
+            terms.push((negative, term));
+            if end == rest.len() {
+                break;
+            }
+            negative = rest.as_bytes()[end] == b'-';
+            rest = &rest[end + 1..];
+        }
+        if !terms
+            .iter()
+            .any(|(_, term)| matches!(term, Term::Dice { .. }))
+        {
+            bail!("rolls no dice");
+        }
+        Ok(Dice { terms })
+    }
+}
+
+impl Dice {
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Roll {
+        let mut dice = Vec::new();
+        let mut modifier = 0;
+        let mut total = 0;
+        for &(negative, ref term) in &self.terms {
+            let sign = if negative { -1 } else { 1 };
+            match *term {
+                Term::Dice { count, sides } => {
+                    let rolls = (0..count)
+                        .map(|_| rng.gen_range(1, u64::from(sides) + 1) as u32)
+                        .collect::<Vec<_>>();
+                    total += sign * rolls.iter().copied().map(i64::from).sum::<i64>();
+                    dice.push(DiceRoll {
+                        dice: format!("{}{}d{}", if negative { "-" } else { "" }, count, sides),
+                        rolls,
+                    });
+                }
+                Term::Modifier(value) => modifier += sign * i64::from(value),
+            }
+        }
+        Roll {
+            dice,
+            modifier,
+            total: total + modifier,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn malformed_notation_is_rejected() {
+        let cases = [
+            "",
+            "   ",
+            "d",
+            "0d6",
+            "3d0",
+            "3d6+",
+            "+",
+            "3d6++2",
+            "2",
+            "-4",
+            "99999999999d6",
+            "3d99999999999",
+            "1001d6",
+            "600d6+600d6",
+            "3d6x",
+            "3d6 2",
+            "3d6d2",
+            "3.5d6",
+            "d-6",
+            "abc",
+        ];
+        for notation in cases.iter() {
+            assert!(notation.parse::<Dice>().is_err(), "{:?} parsed", notation);
+        }
+    }
+
+    #[test]
+    fn rolls_stay_within_the_notation_range() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for &(notation, min, max) in &[
+            ("3d6+2", 5, 20),
+            ("d20-1d4", -3, 19),
+            (" -2D4 + 10 ", 2, 8),
+            ("1000d1", 1000, 1000),
+        ] {
+            let dice = notation.parse::<Dice>().unwrap();
+            for _ in 0..200 {
+                let roll = dice.roll(&mut rng);
+                assert!(
+                    (min..=max).contains(&roll.total),
+                    "{} rolled {}",
+                    notation,
+                    roll.total
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,2150 @@
+//! The distributions `/random` draws from: their parameters, the rules
+//! those are validated against, and the samplers.
+
+use super::*;
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ValueType {
+    Integer,
+    Probability,
+    Real,
+    Boolean,
+    Timestamp,
+    Category,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "distribution", content = "parameters", rename_all = "lowercase")]
+pub enum RngRequest {
+    Uniform(UniformRange),
+    Normal {
+        #[serde(default)]
+        mean: f64,
+        #[serde(default = "default_std_dev")]
+        std_dev: f64,
+    },
+    Bernoulli {
+        #[serde(default = "default_p")]
+        p: f64,
+    },
+    Poisson {
+        #[serde(default = "default_lambda")]
+        lambda: f64,
+    },
+    Exponential {
+        #[serde(default = "default_lambda")]
+        lambda: f64,
+    },
+    Gamma {
+        shape: f64,
+        scale: f64,
+    },
+    Beta {
+        alpha: f64,
+        beta: f64,
+    },
+    #[serde(rename = "log_normal")]
+    LogNormal {
+        #[serde(default)]
+        mean: f64,
+        #[serde(default = "default_std_dev")]
+        std_dev: f64,
+    },
+    Weibull {
+        scale: f64,
+        shape: f64,
+    },
+    Hypergeometric {
+        population: u64,
+        successes: u64,
+        draws: u64,
+    },
+    Dirichlet {
+        alpha: Vec<f64>,
+    },
+    #[serde(rename = "negative_binomial")]
+    NegativeBinomial {
+        r: f64,
+        p: f64,
+    },
+    #[serde(rename = "truncated_normal")]
+    TruncatedNormal {
+        #[serde(default)]
+        mean: f64,
+        #[serde(default = "default_std_dev")]
+        std_dev: f64,
+        low: f64,
+        high: f64,
+    },
+    Mixture {
+        components: Vec<MixtureComponent>,
+    },
+    /// A vector drawn as `mean + L z` for the Cholesky factor `L` of `cov`,
+    /// which is computed on the first draw and reused for the rest of a batch.
+    #[serde(rename = "multivariate_normal")]
+    MultivariateNormal {
+        mean: Vec<f64>,
+        cov: Vec<Vec<f64>>,
+        #[serde(skip)]
+        lower: OnceLock<Option<Vec<Vec<f64>>>>,
+    },
+    Empirical {
+        data: Vec<f64>,
+    },
+    #[serde(rename = "unit_sphere")]
+    UnitSphere {
+        dimensions: u32,
+    },
+    /// An index into `weights`, chosen with probability proportional to its
+    /// weight. Each draw consumes exactly one `f64` from the generator, so a
+    /// client replaying a `seed` and `index` can reproduce it with
+    /// `weighted_choice` on `StdRng::seed_from_u64(seed)` after skipping
+    /// `index` draws.
+    #[serde(rename = "weighted_choice")]
+    WeightedChoice {
+        weights: Vec<f64>,
+    },
+    /// The density through `(x, density)` points, linear between them and
+    /// zero outside, unnormalized.
+    #[serde(rename = "piecewise_linear")]
+    PiecewiseLinear {
+        points: Vec<(f64, f64)>,
+    },
+    /// `n` steps of `x_t = phi * x_{t-1} + eps_t` with `eps_t ~ N(0, sigma^2)`,
+    /// started from the stationary distribution.
+    #[serde(rename = "ar1")]
+    AR1 {
+        phi: f64,
+        sigma: f64,
+        n: u64,
+    },
+    /// A millisecond timestamp uniform over the `hours` of the `weekdays`,
+    /// both in UTC, between `start` and `end` inclusive, each an RFC 3339
+    /// timestamp or a `YYYY-MM-DD` date meaning its UTC midnight.
+    #[serde(rename = "random_date")]
+    RandomDate {
+        start: String,
+        end: String,
+        #[serde(default)]
+        output: DateOutput,
+        #[serde(default = "default_weekdays")]
+        weekdays: Vec<String>,
+        #[serde(default = "default_hours")]
+        hours: Range<u32>,
+    },
+    /// One of `items`, chosen with probability proportional to its entry in
+    /// `weights`, or uniformly when `weights` is omitted.
+    Categorical {
+        items: Vec<Category>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        weights: Option<Vec<f64>>,
+    },
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Category {
+    Number(f64),
+    Text(String),
+}
+
+/// Largest `unit_sphere` dimension or `ar1` length, bounding the memory of a
+/// single draw.
+pub(crate) const MAX_DIMENSIONS: u32 = 65_536;
+
+#[derive(Deserialize, Serialize)]
+pub struct MixtureComponent {
+    weight: f64,
+    request: RngRequest,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum UniformRange {
+    Integer(Range<i32>),
+    Real {
+        #[serde(default)]
+        start: f64,
+        #[serde(default = "default_end")]
+        end: f64,
+    },
+}
+
+fn default_std_dev() -> f64 {
+    1.0
+}
+
+fn default_weekdays() -> Vec<String> {
+    ["mon", "tue", "wed", "thu", "fri", "sat", "sun"]
+        .iter()
+        .map(|&day| day.to_owned())
+        .collect()
+}
+
+fn default_hours() -> Range<u32> {
+    0..24
+}
+
+/// How `random_date` writes its timestamps.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DateOutput {
+    /// An RFC 3339 UTC string.
+    #[default]
+    Rfc3339,
+    /// Whole seconds since the Unix epoch, rounded down.
+    EpochSeconds,
+    EpochMillis,
+}
+
+const DAY_MS: i64 = 86_400_000;
+
+/// The weekdays and UTC hours a `random_date` may fall on, the same every
+/// week.
+struct DateWindows {
+    /// Whether each day from Monday may be drawn.
+    weekdays: [bool; 7],
+    /// The milliseconds of each such day that may be drawn.
+    hours: Range<i64>,
+}
+
+impl DateWindows {
+    fn new(weekdays: &[String], hours: &Range<u32>) -> Option<Self> {
+        if hours.start >= hours.end || hours.end > 24 {
+            return None;
+        }
+        let mut days = [false; 7];
+        for day in weekdays {
+            days[day.parse::<Weekday>().ok()?.num_days_from_monday() as usize] = true;
+        }
+        Some(DateWindows {
+            weekdays: days,
+            hours: i64::from(hours.start) * 3_600_000..i64::from(hours.end) * 3_600_000,
+        })
+    }
+
+    /// How many milliseconds that may be drawn come before `millis`, counted
+    /// from the first Monday after the epoch, which is day 4.
+    fn before(&self, millis: i64) -> i64 {
+        let len = self.hours.end - self.hours.start;
+        let days = |days: &[bool]| days.iter().filter(|&&day| day).count() as i64;
+        let since_monday = millis - 4 * DAY_MS;
+        let weeks = since_monday.div_euclid(7 * DAY_MS);
+        let into_week = since_monday.rem_euclid(7 * DAY_MS);
+        let (day, into_day) = ((into_week / DAY_MS) as usize, into_week % DAY_MS);
+        let today = match self.weekdays[day] {
+            true => (into_day - self.hours.start).clamp(0, len),
+            false => 0,
+        };
+        (weeks * days(&self.weekdays) + days(&self.weekdays[..day])) * len + today
+    }
+
+    /// How many milliseconds from `start` to `end` inclusive may be drawn.
+    fn count(&self, start: i64, end: i64) -> i64 {
+        self.before(end + 1) - self.before(start)
+    }
+
+    /// The `n`th millisecond from `start` that may be drawn, which must be
+    /// below `count(start, end)`.
+    fn nth(&self, start: i64, end: i64, n: i64) -> i64 {
+        let target = self.before(start) + n;
+        let (mut low, mut high) = (start, end);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.before(mid + 1) > target {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        low
+    }
+}
+
+pub(crate) fn default_p() -> f64 {
+    0.5
+}
+
+fn default_lambda() -> f64 {
+    1.0
+}
+
+fn default_end() -> f64 {
+    1.0
+}
+
+impl RngRequest {
+    /// One request per distribution, using default parameters where a
+    /// distribution has them and small valid ones where it does not.
+    pub(crate) fn defaults() -> Vec<RngRequest> {
+        vec![
+            RngRequest::Uniform(UniformRange::Integer(0..10)),
+            RngRequest::Uniform(UniformRange::Real {
+                start: 0.0,
+                end: default_end(),
+            }),
+            RngRequest::Normal {
+                mean: 0.0,
+                std_dev: default_std_dev(),
+            },
+            RngRequest::Bernoulli { p: default_p() },
+            RngRequest::Poisson {
+                lambda: default_lambda(),
+            },
+            RngRequest::Exponential {
+                lambda: default_lambda(),
+            },
+            RngRequest::Gamma {
+                shape: 2.0,
+                scale: 1.0,
+            },
+            RngRequest::Beta {
+                alpha: 2.0,
+                beta: 2.0,
+            },
+            RngRequest::LogNormal {
+                mean: 0.0,
+                std_dev: default_std_dev(),
+            },
+            RngRequest::Weibull {
+                scale: 1.0,
+                shape: 1.5,
+            },
+            RngRequest::Hypergeometric {
+                population: 10,
+                successes: 5,
+                draws: 3,
+            },
+            RngRequest::Dirichlet {
+                alpha: vec![1.0, 1.0, 1.0],
+            },
+            RngRequest::NegativeBinomial { r: 1.0, p: 0.5 },
+            RngRequest::TruncatedNormal {
+                mean: 0.0,
+                std_dev: default_std_dev(),
+                low: -1.0,
+                high: 1.0,
+            },
+            RngRequest::Mixture {
+                components: vec![
+                    MixtureComponent {
+                        weight: 0.7,
+                        request: RngRequest::Normal {
+                            mean: 0.0,
+                            std_dev: default_std_dev(),
+                        },
+                    },
+                    MixtureComponent {
+                        weight: 0.3,
+                        request: RngRequest::Normal {
+                            mean: 5.0,
+                            std_dev: default_std_dev(),
+                        },
+                    },
+                ],
+            },
+            RngRequest::MultivariateNormal {
+                mean: vec![0.0, 0.0],
+                cov: vec![vec![1.0, 0.5], vec![0.5, 1.0]],
+                lower: OnceLock::new(),
+            },
+            RngRequest::Empirical {
+                data: vec![1.0, 2.0, 3.0],
+            },
+            RngRequest::UnitSphere { dimensions: 3 },
+            RngRequest::WeightedChoice {
+                weights: vec![1.0, 1.0, 2.0],
+            },
+            RngRequest::PiecewiseLinear {
+                points: vec![(0.0, 0.0), (1.0, 2.0)],
+            },
+            RngRequest::AR1 {
+                phi: 0.5,
+                sigma: 1.0,
+                n: 10,
+            },
+            RngRequest::RandomDate {
+                start: "2000-01-01".to_owned(),
+                end: "2000-12-31T23:59:59Z".to_owned(),
+                output: DateOutput::Rfc3339,
+                weekdays: default_weekdays()[..5].to_vec(),
+                hours: 9..17,
+            },
+            RngRequest::Categorical {
+                items: ["heads", "tails", "edge"]
+                    .iter()
+                    .map(|&item| Category::Text(item.to_owned()))
+                    .collect(),
+                weights: Some(vec![49.5, 49.5, 1.0]),
+            },
+        ]
+    }
+
+    pub(crate) fn value_type(&self) -> ValueType {
+        match self {
+            RngRequest::Uniform(UniformRange::Integer(_)) => ValueType::Integer,
+            RngRequest::Uniform(UniformRange::Real { .. }) => ValueType::Real,
+            RngRequest::Normal { .. } => ValueType::Real,
+            RngRequest::Bernoulli { .. } => ValueType::Boolean,
+            RngRequest::Poisson { .. } => ValueType::Integer,
+            RngRequest::Exponential { .. } => ValueType::Real,
+            RngRequest::Gamma { .. } => ValueType::Real,
+            RngRequest::Beta { .. } => ValueType::Probability,
+            RngRequest::LogNormal { .. } => ValueType::Real,
+            RngRequest::Weibull { .. } => ValueType::Real,
+            RngRequest::Hypergeometric { .. } => ValueType::Integer,
+            RngRequest::Dirichlet { .. } => ValueType::Probability,
+            RngRequest::NegativeBinomial { .. } => ValueType::Integer,
+            RngRequest::TruncatedNormal { .. } => ValueType::Real,
+            RngRequest::Mixture { ref components } => {
+                let mut types = components
+                    .iter()
+                    .map(|component| component.request.value_type());
+                let first = types.next().unwrap_or(ValueType::Real);
+                if types.all(|kind| kind == first) {
+                    first
+                } else {
+                    ValueType::Real
+                }
+            }
+            RngRequest::MultivariateNormal { .. } => ValueType::Real,
+            RngRequest::Empirical { .. } => ValueType::Real,
+            RngRequest::UnitSphere { .. } => ValueType::Real,
+            RngRequest::WeightedChoice { .. } => ValueType::Integer,
+            RngRequest::PiecewiseLinear { .. } => ValueType::Real,
+            RngRequest::AR1 { .. } => ValueType::Real,
+            RngRequest::RandomDate { output, .. } => match output {
+                DateOutput::Rfc3339 => ValueType::Timestamp,
+                DateOutput::EpochSeconds | DateOutput::EpochMillis => ValueType::Integer,
+            },
+            RngRequest::Categorical { ref items, .. } => {
+                if items.iter().all(|item| matches!(item, Category::Number(_))) {
+                    ValueType::Real
+                } else {
+                    ValueType::Category
+                }
+            }
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            RngRequest::Uniform(_) => "uniform",
+            RngRequest::Normal { .. } => "normal",
+            RngRequest::Bernoulli { .. } => "bernoulli",
+            RngRequest::Poisson { .. } => "poisson",
+            RngRequest::Exponential { .. } => "exponential",
+            RngRequest::Gamma { .. } => "gamma",
+            RngRequest::Beta { .. } => "beta",
+            RngRequest::LogNormal { .. } => "log_normal",
+            RngRequest::Weibull { .. } => "weibull",
+            RngRequest::Hypergeometric { .. } => "hypergeometric",
+            RngRequest::Dirichlet { .. } => "dirichlet",
+            RngRequest::NegativeBinomial { .. } => "negative_binomial",
+            RngRequest::TruncatedNormal { .. } => "truncated_normal",
+            RngRequest::Mixture { .. } => "mixture",
+            RngRequest::MultivariateNormal { .. } => "multivariate_normal",
+            RngRequest::Empirical { .. } => "empirical",
+            RngRequest::UnitSphere { .. } => "unit_sphere",
+            RngRequest::WeightedChoice { .. } => "weighted_choice",
+            RngRequest::PiecewiseLinear { .. } => "piecewise_linear",
+            RngRequest::AR1 { .. } => "ar1",
+            RngRequest::RandomDate { .. } => "random_date",
+            RngRequest::Categorical { .. } => "categorical",
+        }
+    }
+
+    pub(crate) fn quantile(&self, p: f64) -> Option<f64> {
+        match *self {
+            RngRequest::Uniform(UniformRange::Integer(ref range)) => {
+                let n = f64::from(range.end) - f64::from(range.start);
+                Some(f64::from(range.start) + ((p * n).ceil() - 1.0).max(0.0))
+            }
+            RngRequest::Uniform(UniformRange::Real { start, end }) => {
+                Some(start + p * (end - start))
+            }
+            RngRequest::Normal { mean, std_dev } => {
+                Some(mean + std_dev * stats::normal_quantile(p))
+            }
+            RngRequest::Bernoulli { p: success } => {
+                Some(if p <= 1.0 - success { 0.0 } else { 1.0 })
+            }
+            RngRequest::Exponential { lambda } => Some(-(1.0 - p).ln() / lambda),
+            RngRequest::LogNormal { mean, std_dev } => {
+                Some((mean + std_dev * stats::normal_quantile(p)).exp())
+            }
+            RngRequest::Weibull { scale, shape } => {
+                Some(scale * (-(1.0 - p).ln()).powf(1.0 / shape))
+            }
+            RngRequest::WeightedChoice { ref weights } => {
+                let total = weights.iter().sum::<f64>();
+                let mut cumulative = 0.0;
+                let index = weights.iter().position(|weight| {
+                    cumulative += weight;
+                    *weight > 0.0 && cumulative >= p * total
+                });
+                Some(index.unwrap_or(weights.len() - 1) as f64)
+            }
+            RngRequest::PiecewiseLinear { ref points } => {
+                Some(piecewise_linear_quantile(points, p))
+            }
+            RngRequest::Poisson { .. }
+            | RngRequest::Gamma { .. }
+            | RngRequest::Beta { .. }
+            | RngRequest::Hypergeometric { .. }
+            | RngRequest::Dirichlet { .. }
+            | RngRequest::NegativeBinomial { .. }
+            | RngRequest::TruncatedNormal { .. }
+            | RngRequest::Mixture { .. }
+            | RngRequest::MultivariateNormal { .. }
+            | RngRequest::Empirical { .. }
+            | RngRequest::UnitSphere { .. }
+            | RngRequest::AR1 { .. }
+            | RngRequest::RandomDate { .. }
+            | RngRequest::Categorical { .. } => None,
+        }
+    }
+
+    /// The inverse CDF applied to a uniform draw in [0, 1), for the
+    /// distributions `show_uniform` and `antithetic` can sample by inverse
+    /// transform.
+    pub(crate) fn inverse_transform(&self) -> Option<Box<dyn Fn(f64) -> f64>> {
+        match *self {
+            RngRequest::Uniform(UniformRange::Integer(ref range)) => {
+                let start = f64::from(range.start);
+                let n = f64::from(range.end) - start;
+                Some(Box::new(move |u| start + (u * n).floor().min(n - 1.0)))
+            }
+            RngRequest::Uniform(UniformRange::Real { start, end }) => {
+                Some(Box::new(move |u| start + u * (end - start)))
+            }
+            RngRequest::Exponential { lambda } => Some(Box::new(move |u| -(1.0 - u).ln() / lambda)),
+            RngRequest::Weibull { scale, shape } => Some(Box::new(move |u| {
+                scale * (-(1.0 - u).ln()).powf(1.0 / shape)
+            })),
+            RngRequest::WeightedChoice { ref weights } => {
+                let weights = weights.clone();
+                Some(Box::new(move |u| weighted_choice(&weights, u) as f64))
+            }
+            RngRequest::PiecewiseLinear { ref points } => {
+                let points = points.clone();
+                Some(Box::new(move |u| piecewise_linear_quantile(&points, u)))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn cdf(&self, x: f64) -> Option<f64> {
+        let probability = match *self {
+            RngRequest::Uniform(UniformRange::Integer(ref range)) => {
+                let n = f64::from(range.end) - f64::from(range.start);
+                (x.floor() - f64::from(range.start) + 1.0) / n
+            }
+            RngRequest::Uniform(UniformRange::Real { start, end }) => (x - start) / (end - start),
+            RngRequest::Normal { mean, std_dev: 0.0 } => {
+                if x >= mean {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            RngRequest::Normal { mean, std_dev } => stats::normal_cdf((x - mean) / std_dev),
+            RngRequest::Bernoulli { p } => {
+                if x < 0.0 {
+                    0.0
+                } else if x < 1.0 {
+                    1.0 - p
+                } else {
+                    1.0
+                }
+            }
+            RngRequest::Exponential { lambda } => 1.0 - (-lambda * x.max(0.0)).exp(),
+            RngRequest::LogNormal { mean, std_dev } if x <= 0.0 || std_dev == 0.0 => {
+                if x >= mean.exp() {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            RngRequest::LogNormal { mean, std_dev } => stats::normal_cdf((x.ln() - mean) / std_dev),
+            RngRequest::Weibull { scale, shape } => 1.0 - (-(x.max(0.0) / scale).powf(shape)).exp(),
+            RngRequest::Poisson { .. }
+            | RngRequest::Gamma { .. }
+            | RngRequest::Beta { .. }
+            | RngRequest::Hypergeometric { .. }
+            | RngRequest::Dirichlet { .. }
+            | RngRequest::NegativeBinomial { .. }
+            | RngRequest::TruncatedNormal { .. }
+            | RngRequest::MultivariateNormal { .. }
+            | RngRequest::UnitSphere { .. }
+            | RngRequest::AR1 { .. }
+            | RngRequest::RandomDate { .. }
+            | RngRequest::Categorical { .. } => return None,
+            RngRequest::Empirical { ref data } => {
+                data.iter().filter(|&&value| value <= x).count() as f64 / data.len() as f64
+            }
+            RngRequest::WeightedChoice { ref weights } => {
+                let below = weights
+                    .iter()
+                    .take((x.floor() + 1.0).max(0.0) as usize)
+                    .sum::<f64>();
+                below / weights.iter().sum::<f64>()
+            }
+            RngRequest::PiecewiseLinear { ref points } => {
+                let areas = trapezoid_areas(points);
+                let below = areas
+                    .iter()
+                    .zip(points.windows(2))
+                    .map(|(&area, segment)| {
+                        let ((x0, d0), (x1, d1)) = (segment[0], segment[1]);
+                        if x >= x1 {
+                            area
+                        } else if x > x0 {
+                            let t = x - x0;
+                            let density = d0 + (d1 - d0) * t / (x1 - x0);
+                            (d0 + density) / 2.0 * t
+                        } else {
+                            0.0
+                        }
+                    })
+                    .sum::<f64>();
+                below / areas.iter().sum::<f64>()
+            }
+            RngRequest::Mixture { ref components } => {
+                let total = components
+                    .iter()
+                    .map(|component| component.weight)
+                    .sum::<f64>();
+                let mut probability = 0.0;
+                for component in components {
+                    probability += component.weight / total * component.request.cdf(x)?;
+                }
+                probability
+            }
+        };
+        Some(probability.clamp(0.0, 1.0))
+    }
+
+    /// The probability density at `x`, for continuous distributions with a
+    /// closed form.
+    pub(crate) fn density(&self, x: f64) -> Option<f64> {
+        match *self {
+            RngRequest::Uniform(UniformRange::Real { start, end }) => {
+                Some(if (start..end).contains(&x) {
+                    1.0 / (end - start)
+                } else {
+                    0.0
+                })
+            }
+            RngRequest::Normal { std_dev: 0.0, .. } => None,
+            RngRequest::Normal { mean, std_dev } => {
+                Some(stats::normal_pdf((x - mean) / std_dev) / std_dev)
+            }
+            RngRequest::Exponential { lambda } => Some(if x < 0.0 {
+                0.0
+            } else {
+                lambda * (-lambda * x).exp()
+            }),
+            RngRequest::Gamma { shape, scale } => Some(if x <= 0.0 {
+                0.0
+            } else {
+                ((shape - 1.0) * x.ln() - x / scale - stats::ln_gamma(shape) - shape * scale.ln())
+                    .exp()
+            }),
+            RngRequest::Beta { alpha, beta } => Some(if x <= 0.0 || x >= 1.0 {
+                0.0
+            } else {
+                let ln_beta =
+                    stats::ln_gamma(alpha) + stats::ln_gamma(beta) - stats::ln_gamma(alpha + beta);
+                ((alpha - 1.0) * x.ln() + (beta - 1.0) * (1.0 - x).ln() - ln_beta).exp()
+            }),
+            RngRequest::LogNormal { std_dev: 0.0, .. } => None,
+            RngRequest::LogNormal { mean, std_dev } => Some(if x <= 0.0 {
+                0.0
+            } else {
+                stats::normal_pdf((x.ln() - mean) / std_dev) / (std_dev * x)
+            }),
+            RngRequest::Weibull { scale, shape } => Some(if x < 0.0 {
+                0.0
+            } else {
+                let z = x / scale;
+                shape / scale * z.powf(shape - 1.0) * (-z.powf(shape)).exp()
+            }),
+            RngRequest::TruncatedNormal { std_dev: 0.0, .. } => None,
+            RngRequest::TruncatedNormal {
+                mean,
+                std_dev,
+                low,
+                high,
+            } => {
+                if !(low..=high).contains(&x) {
+                    return Some(0.0);
+                }
+                let mass = stats::normal_cdf((high - mean) / std_dev)
+                    - stats::normal_cdf((low - mean) / std_dev);
+                Some(stats::normal_pdf((x - mean) / std_dev) / std_dev / mass)
+            }
+            RngRequest::Mixture { ref components } => {
+                let total = components
+                    .iter()
+                    .map(|component| component.weight)
+                    .sum::<f64>();
+                let mut density = 0.0;
+                for component in components {
+                    density += component.weight / total * component.request.density(x)?;
+                }
+                Some(density)
+            }
+            RngRequest::PiecewiseLinear { ref points } => {
+                let total = trapezoid_areas(points).iter().sum::<f64>();
+                let density = points
+                    .windows(2)
+                    .find(|segment| (segment[0].0..=segment[1].0).contains(&x))
+                    .map_or(0.0, |segment| {
+                        let ((x0, d0), (x1, d1)) = (segment[0], segment[1]);
+                        d0 + (d1 - d0) * (x - x0) / (x1 - x0)
+                    });
+                Some(density / total)
+            }
+            _ => None,
+        }
+    }
+
+    fn float_parameters(&self) -> Vec<(&'static str, f64)> {
+        match *self {
+            RngRequest::Uniform(UniformRange::Integer(_))
+            | RngRequest::Hypergeometric { .. }
+            | RngRequest::UnitSphere { .. }
+            | RngRequest::RandomDate { .. } => vec![],
+            RngRequest::Uniform(UniformRange::Real { start, end }) => {
+                vec![("start", start), ("end", end)]
+            }
+            RngRequest::Normal { mean, std_dev } => vec![("mean", mean), ("std_dev", std_dev)],
+            RngRequest::Bernoulli { p } => vec![("p", p)],
+            RngRequest::Poisson { lambda } | RngRequest::Exponential { lambda } => {
+                vec![("lambda", lambda)]
+            }
+            RngRequest::Gamma { shape, scale } | RngRequest::Weibull { scale, shape } => {
+                vec![("shape", shape), ("scale", scale)]
+            }
+            RngRequest::Beta { alpha, beta } => vec![("alpha", alpha), ("beta", beta)],
+            RngRequest::LogNormal { mean, std_dev } => vec![("mean", mean), ("std_dev", std_dev)],
+            RngRequest::Dirichlet { ref alpha } => {
+                alpha.iter().map(|&alpha| ("alpha", alpha)).collect()
+            }
+            RngRequest::NegativeBinomial { r, p } => vec![("r", r), ("p", p)],
+            RngRequest::TruncatedNormal {
+                mean,
+                std_dev,
+                low,
+                high,
+            } => vec![
+                ("mean", mean),
+                ("std_dev", std_dev),
+                ("low", low),
+                ("high", high),
+            ],
+            RngRequest::Mixture { ref components } => components
+                .iter()
+                .flat_map(|component| {
+                    let mut parameters = component.request.float_parameters();
+                    parameters.insert(0, ("weight", component.weight));
+                    parameters
+                })
+                .collect(),
+            RngRequest::MultivariateNormal {
+                ref mean, ref cov, ..
+            } => mean
+                .iter()
+                .map(|&mean| ("mean", mean))
+                .chain(cov.iter().flatten().map(|&cov| ("cov", cov)))
+                .collect(),
+            RngRequest::Empirical { ref data } => data.iter().map(|&data| ("data", data)).collect(),
+            RngRequest::WeightedChoice { ref weights } => {
+                weights.iter().map(|&weight| ("weights", weight)).collect()
+            }
+            RngRequest::PiecewiseLinear { ref points } => points
+                .iter()
+                .flat_map(|&(x, density)| [("points", x), ("points", density)])
+                .collect(),
+            RngRequest::AR1 { phi, sigma, .. } => vec![("phi", phi), ("sigma", sigma)],
+            RngRequest::Categorical {
+                ref items,
+                ref weights,
+            } => items
+                .iter()
+                .filter_map(|item| match *item {
+                    Category::Number(item) => Some(("items", item)),
+                    Category::Text(_) => None,
+                })
+                .chain(weights.iter().flatten().map(|&weight| ("weights", weight)))
+                .collect(),
+        }
+    }
+
+    /// Moves bounded parameters onto the nearest valid value, returning each
+    /// adjusted parameter with its old and new value.
+    fn clamp(&mut self) -> Vec<(&'static str, f64, f64)> {
+        let mut clamped = Vec::new();
+        let mut nested = Vec::new();
+        let mut clamp = |name, value: &mut f64, min: f64, max: f64| {
+            let old = *value;
+            *value = value.clamp(min, max);
+            if *value != old {
+                clamped.push((name, old, *value));
+            }
+        };
+        match *self {
+            RngRequest::Normal {
+                ref mut std_dev, ..
+            }
+            | RngRequest::TruncatedNormal {
+                ref mut std_dev, ..
+            }
+            | RngRequest::LogNormal {
+                ref mut std_dev, ..
+            } => clamp("std_dev", std_dev, 0.0, f64::INFINITY),
+            RngRequest::AR1 { ref mut sigma, .. } => clamp("sigma", sigma, 0.0, f64::INFINITY),
+            RngRequest::Bernoulli { ref mut p } => clamp("p", p, 0.0, 1.0),
+            RngRequest::NegativeBinomial { ref mut p, .. } => clamp("p", p, f64::NEG_INFINITY, 1.0),
+            RngRequest::Hypergeometric {
+                population,
+                ref mut successes,
+                ref mut draws,
+            } => {
+                for (name, value) in [("successes", successes), ("draws", draws)] {
+                    if *value > population {
+                        clamped.push((name, *value as f64, population as f64));
+                        *value = population;
+                    }
+                }
+            }
+            RngRequest::Mixture { ref mut components } => {
+                for component in components.iter_mut() {
+                    clamp("weight", &mut component.weight, 0.0, f64::INFINITY);
+                }
+                nested = components
+                    .iter_mut()
+                    .flat_map(|component| component.request.clamp())
+                    .collect();
+            }
+            RngRequest::WeightedChoice { ref mut weights }
+            | RngRequest::Categorical {
+                weights: Some(ref mut weights),
+                ..
+            } => {
+                for weight in weights.iter_mut() {
+                    clamp("weights", weight, 0.0, f64::INFINITY);
+                }
+            }
+            RngRequest::PiecewiseLinear { ref mut points } => {
+                for (_, density) in points.iter_mut() {
+                    clamp("points", density, 0.0, f64::INFINITY);
+                }
+            }
+            _ => {}
+        }
+        clamped.extend(nested);
+        clamped
+    }
+
+    /// Applies `param_policy` and the magnitude limit, then validates.
+    pub(crate) fn enforce(&mut self, config: &Config) -> Result<(), Error> {
+        if config.param_policy == ParamPolicy::Clamp {
+            for (name, old, new) in self.clamp() {
+                warn!("clamped {} {} of {} to {}", self.name(), name, old, new);
+            }
+        }
+        self.check_magnitudes(config.max_param_magnitude)?;
+        self.validate()?;
+        let problems = self.limit_problems(config);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidParameters(problems).into())
+        }
+    }
+
+    /// Parameters that are valid but over a configured limit on how long
+    /// sampling may take.
+    fn limit_problems(&self, config: &Config) -> Vec<FieldError> {
+        match *self {
+            RngRequest::Hypergeometric { population, .. }
+                if population > config.max_hypergeometric_population =>
+            {
+                vec![FieldError {
+                    field: "population".to_owned(),
+                    problem: format!("must be at most {}", config.max_hypergeometric_population),
+                }]
+            }
+            RngRequest::Mixture { ref components } => components
+                .iter()
+                .enumerate()
+                .flat_map(|(i, component)| {
+                    component
+                        .request
+                        .limit_problems(config)
+                        .into_iter()
+                        .map(move |problem| FieldError {
+                            field: format!(
+                                "components[{}].request.parameters.{}",
+                                i, problem.field
+                            ),
+                            problem: problem.problem,
+                        })
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Approximate memory one sample of this distribution occupies in a batch.
+    pub(crate) fn sample_bytes(&self) -> usize {
+        match *self {
+            RngRequest::Dirichlet { ref alpha } => {
+                mem::size_of::<Sample>() + alpha.len() * mem::size_of::<f64>()
+            }
+            RngRequest::MultivariateNormal { ref mean, .. } => {
+                mem::size_of::<Sample>() + mean.len() * mem::size_of::<f64>()
+            }
+            RngRequest::UnitSphere { dimensions } => {
+                mem::size_of::<Sample>() + dimensions as usize * mem::size_of::<f64>()
+            }
+            RngRequest::AR1 { n, .. } => {
+                mem::size_of::<Sample>() + n as usize * mem::size_of::<f64>()
+            }
+            RngRequest::Mixture { ref components } => components
+                .iter()
+                .map(|component| component.request.sample_bytes())
+                .max()
+                .unwrap_or(0),
+            _ => mem::size_of::<Sample>(),
+        }
+    }
+
+    /// Silverman's rule-of-thumb kernel width for smoothing `empirical` draws.
+    pub(crate) fn bandwidth(&self) -> Option<f64> {
+        match *self {
+            RngRequest::Empirical { ref data } => {
+                let n = data.len() as f64;
+                let mean = data.iter().sum::<f64>() / n;
+                let variance = data.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+                Some(1.06 * variance.sqrt() * n.powf(-0.2))
+            }
+            _ => None,
+        }
+    }
+
+    fn check_magnitudes(&self, max: f64) -> Result<(), Error> {
+        for (name, value) in self.float_parameters() {
+            check_magnitude(name, value, max)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), Error> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidParameters(problems).into())
+        }
+    }
+
+    /// Every invalid parameter, so a client can flag all of them at once.
+    fn problems(&self) -> Vec<FieldError> {
+        let mut problems = self
+            .rules()
+            .into_iter()
+            .filter(|rule| !rule.valid)
+            .map(|rule| FieldError {
+                field: rule.field.to_owned(),
+                problem: rule.constraint.problem(),
+            })
+            .collect::<Vec<_>>();
+        if let RngRequest::Mixture { ref components } = *self {
+            for (i, component) in components.iter().enumerate() {
+                problems.extend(component.request.problems().into_iter().map(|problem| {
+                    FieldError {
+                        field: format!("components[{}].request.parameters.{}", i, problem.field),
+                        problem: problem.problem,
+                    }
+                }));
+            }
+        }
+        problems
+    }
+
+    /// Parameter metadata for `/distributions`, read off this request's own
+    /// serialization, deserialization defaults and rules so it cannot drift
+    /// from what requests actually accept.
+    pub(crate) fn describe(&self) -> DistributionInfo {
+        let example = serde_json::to_value(self).unwrap();
+        let mut names = example["parameters"]
+            .as_object()
+            .map(|parameters| parameters.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let rules = self.rules();
+        for rule in &rules {
+            if !names.iter().any(|name| name == rule.field) {
+                names.push(rule.field.to_owned());
+            }
+        }
+        let parameters = names
+            .into_iter()
+            .map(|name| ParameterInfo {
+                kind: json_path(&example["parameters"], &name).map_or_else(String::new, json_type),
+                default: self.default_for(&example, &name),
+                constraints: rules
+                    .iter()
+                    .filter(|rule| rule.field == name)
+                    .map(|rule| rule.constraint)
+                    .collect(),
+                name,
+            })
+            .collect();
+        DistributionInfo {
+            name: self.name(),
+            value_type: self.value_type(),
+            parameters,
+        }
+    }
+
+    /// The value deserialization fills in when `field` is left out, if that
+    /// still yields this kind of request.
+    fn default_for(&self, example: &Value, field: &str) -> Option<Value> {
+        let mut request = example.clone();
+        request
+            .get_mut("parameters")?
+            .as_object_mut()?
+            .remove(field)?;
+        default_parameters(&mut request);
+        let defaulted = serde_json::from_value::<RngRequest>(request).ok()?;
+        if defaulted.name() != self.name() || defaulted.value_type() != self.value_type() {
+            return None;
+        }
+        serde_json::to_value(defaulted)
+            .ok()?
+            .get("parameters")?
+            .get(field)
+            .cloned()
+    }
+
+    /// The constraints on this request's parameters, each checked against its
+    /// current values. `/distributions` lists them for the default requests.
+    fn rules(&self) -> Vec<Rule> {
+        match *self {
+            RngRequest::Uniform(UniformRange::Integer(ref range)) => vec![Rule::compare(
+                "start",
+                f64::from(range.start),
+                Constraint::LessThan { field: "end" },
+                f64::from(range.end),
+            )],
+            RngRequest::Uniform(UniformRange::Real { start, end }) => vec![Rule::compare(
+                "start",
+                start,
+                Constraint::LessThan { field: "end" },
+                end,
+            )],
+            RngRequest::Normal { std_dev, .. } => {
+                vec![Rule::each("std_dev", Constraint::at_least(0.0), [std_dev])]
+            }
+            RngRequest::Bernoulli { p } => {
+                vec![Rule::each("p", Constraint::between(0.0, 1.0), [p])]
+            }
+            RngRequest::Poisson { lambda } | RngRequest::Exponential { lambda } => {
+                vec![Rule::each(
+                    "lambda",
+                    Constraint::greater_than(0.0),
+                    [lambda],
+                )]
+            }
+            RngRequest::Gamma { shape, scale } | RngRequest::Weibull { scale, shape } => vec![
+                Rule::each("shape", Constraint::greater_than(0.0), [shape]),
+                Rule::each("scale", Constraint::greater_than(0.0), [scale]),
+            ],
+            RngRequest::Beta { alpha, beta } => vec![
+                Rule::each("alpha", Constraint::greater_than(0.0), [alpha]),
+                Rule::each("beta", Constraint::greater_than(0.0), [beta]),
+            ],
+            RngRequest::LogNormal { std_dev, .. } => {
+                vec![Rule::each("std_dev", Constraint::at_least(0.0), [std_dev])]
+            }
+            RngRequest::Hypergeometric {
+                population,
+                successes,
+                draws,
+            } => vec![
+                Rule::compare(
+                    "successes",
+                    successes as f64,
+                    Constraint::AtMost {
+                        field: "population",
+                    },
+                    population as f64,
+                ),
+                Rule::compare(
+                    "draws",
+                    draws as f64,
+                    Constraint::AtMost {
+                        field: "population",
+                    },
+                    population as f64,
+                ),
+            ],
+            RngRequest::Dirichlet { ref alpha } => vec![
+                Rule::length("alpha", alpha.len(), 1),
+                Rule::each(
+                    "alpha",
+                    Constraint::greater_than(0.0),
+                    alpha.iter().copied(),
+                ),
+            ],
+            RngRequest::NegativeBinomial { r, p } => vec![
+                Rule::each("r", Constraint::greater_than(0.0), [r]),
+                Rule::each(
+                    "p",
+                    Constraint::Range {
+                        min: Some(0.0),
+                        min_exclusive: true,
+                        max: Some(1.0),
+                        max_exclusive: false,
+                    },
+                    [p],
+                ),
+            ],
+            RngRequest::TruncatedNormal {
+                std_dev, low, high, ..
+            } => vec![
+                Rule::each("std_dev", Constraint::at_least(0.0), [std_dev]),
+                Rule::compare("low", low, Constraint::LessThan { field: "high" }, high),
+            ],
+            RngRequest::Mixture { ref components } => {
+                let weights = components.iter().map(|component| component.weight);
+                vec![
+                    Rule::length("components", components.len(), 1),
+                    Rule::each(
+                        "components[].weight",
+                        Constraint::at_least(0.0),
+                        weights.clone(),
+                    ),
+                    Rule::sum("components[].weight", weights),
+                ]
+            }
+            RngRequest::MultivariateNormal {
+                ref mean, ref cov, ..
+            } => {
+                let square =
+                    cov.len() == mean.len() && cov.iter().all(|row| row.len() == mean.len());
+                vec![
+                    Rule::length("mean", mean.len(), 1),
+                    Rule::custom(
+                        "cov",
+                        "must be a square matrix matching the length of mean",
+                        square,
+                    ),
+                    Rule::custom(
+                        "cov",
+                        "must be symmetric positive semi-definite",
+                        !square || stats::cholesky(cov).is_some(),
+                    ),
+                ]
+            }
+            RngRequest::Empirical { ref data } => vec![Rule::length("data", data.len(), 1)],
+            RngRequest::UnitSphere { dimensions } => vec![Rule::each(
+                "dimensions",
+                Constraint::between(1.0, f64::from(MAX_DIMENSIONS)),
+                [f64::from(dimensions)],
+            )],
+            RngRequest::WeightedChoice { ref weights } => vec![
+                Rule::length("weights", weights.len(), 1),
+                Rule::each(
+                    "weights",
+                    Constraint::at_least(0.0),
+                    weights.iter().copied(),
+                ),
+                Rule::sum("weights", weights.iter().copied()),
+            ],
+            RngRequest::PiecewiseLinear { ref points } => vec![
+                Rule::length("points", points.len(), 2),
+                Rule::custom(
+                    "points",
+                    "must be sorted by strictly increasing x",
+                    points.windows(2).all(|segment| segment[0].0 < segment[1].0),
+                ),
+                Rule::custom(
+                    "points",
+                    "must have non-negative densities",
+                    points.iter().all(|&(_, density)| density >= 0.0),
+                ),
+                Rule::custom(
+                    "points",
+                    "must enclose a positive area",
+                    points.len() < 2 || trapezoid_areas(points).iter().sum::<f64>() > 0.0,
+                ),
+            ],
+            RngRequest::AR1 { phi, sigma, n } => vec![
+                Rule::each(
+                    "phi",
+                    Constraint::Range {
+                        min: Some(-1.0),
+                        min_exclusive: true,
+                        max: Some(1.0),
+                        max_exclusive: true,
+                    },
+                    [phi],
+                ),
+                Rule::each("sigma", Constraint::at_least(0.0), [sigma]),
+                Rule::each(
+                    "n",
+                    Constraint::between(1.0, f64::from(MAX_DIMENSIONS)),
+                    [n as f64],
+                ),
+            ],
+            RngRequest::RandomDate {
+                ref start,
+                ref end,
+                ref weekdays,
+                ref hours,
+                ..
+            } => {
+                let (start, end) = (parse_timestamp(start), parse_timestamp(end));
+                let format = "must be an RFC 3339 timestamp or YYYY-MM-DD date";
+                let mut rules = vec![
+                    Rule::custom("start", format, start.is_some()),
+                    Rule::custom("end", format, end.is_some()),
+                    Rule::custom(
+                        "weekdays",
+                        "must be day names such as mon or monday",
+                        weekdays.iter().all(|day| day.parse::<Weekday>().is_ok()),
+                    ),
+                    Rule::custom(
+                        "hours",
+                        "must have start below end and end at most 24",
+                        hours.start < hours.end && hours.end <= 24,
+                    ),
+                ];
+                if let (Some(start), Some(end)) = (start, end) {
+                    let (start, end) = (start.timestamp_millis(), end.timestamp_millis());
+                    rules.push(Rule::compare(
+                        "start",
+                        start as f64,
+                        Constraint::AtMost { field: "end" },
+                        end as f64,
+                    ));
+                    if let (Some(windows), true) = (DateWindows::new(weekdays, hours), start <= end)
+                    {
+                        rules.push(Rule::custom(
+                            "weekdays",
+                            "must include some of their hours between start and end",
+                            windows.count(start, end) > 0,
+                        ));
+                    }
+                }
+                rules
+            }
+            RngRequest::Categorical {
+                ref items,
+                ref weights,
+            } => {
+                let mut rules = vec![Rule::length("items", items.len(), 1)];
+                if let Some(ref weights) = *weights {
+                    rules.push(Rule::custom(
+                        "weights",
+                        "must have one entry per item",
+                        weights.len() == items.len(),
+                    ));
+                    rules.push(Rule::each(
+                        "weights",
+                        Constraint::at_least(0.0),
+                        weights.iter().copied(),
+                    ));
+                    rules.push(Rule::sum("weights", weights.iter().copied()));
+                }
+                rules
+            }
+        }
+    }
+}
+
+pub(crate) fn check_magnitude(name: &str, value: f64, max: f64) -> Result<(), Error> {
+    if !value.is_finite() {
+        bail!("{} must be finite, got {}", name, value);
+    }
+    if value.abs() > max {
+        bail!(
+            "{} {:e} exceeds the maximum magnitude of {:e}",
+            name,
+            value,
+            max
+        );
+    }
+    Ok(())
+}
+
+/// Follows a `/distributions` field such as `components[].weight` into a
+/// value, taking the first element of each array.
+fn json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |value, segment| match segment.strip_suffix("[]") {
+            Some(field) => value.get(field)?.get(0),
+            None => value.get(segment),
+        })
+}
+
+pub(crate) fn json_type(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_owned(),
+        Value::Bool(_) => "boolean".to_owned(),
+        Value::Number(number) if number.is_f64() => "number".to_owned(),
+        Value::Number(_) => "integer".to_owned(),
+        Value::String(_) => "string".to_owned(),
+        Value::Array(values) => match values.first() {
+            Some(value) => format!("array<{}>", json_type(value)),
+            None => "array".to_owned(),
+        },
+        Value::Object(_) => "object".to_owned(),
+    }
+}
+
+/// A rule a parameter must satisfy, described as data so `/distributions`
+/// publishes exactly what `validate` enforces.
+#[derive(Clone, Copy, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Constraint {
+    /// Bounds on the parameter, or on every element of an array parameter.
+    Range {
+        min: Option<f64>,
+        min_exclusive: bool,
+        max: Option<f64>,
+        max_exclusive: bool,
+    },
+    LessThan {
+        field: &'static str,
+    },
+    AtMost {
+        field: &'static str,
+    },
+    MinLength {
+        length: usize,
+    },
+    PositiveSum,
+    Custom {
+        description: &'static str,
+    },
+}
+
+impl Constraint {
+    fn at_least(min: f64) -> Self {
+        Constraint::Range {
+            min: Some(min),
+            min_exclusive: false,
+            max: None,
+            max_exclusive: false,
+        }
+    }
+
+    fn greater_than(min: f64) -> Self {
+        Constraint::Range {
+            min: Some(min),
+            min_exclusive: true,
+            max: None,
+            max_exclusive: false,
+        }
+    }
+
+    fn between(min: f64, max: f64) -> Self {
+        Constraint::Range {
+            min: Some(min),
+            min_exclusive: false,
+            max: Some(max),
+            max_exclusive: false,
+        }
+    }
+
+    fn holds(&self, value: f64) -> bool {
+        match *self {
+            Constraint::Range {
+                min,
+                min_exclusive,
+                max,
+                max_exclusive,
+            } => {
+                let above = min.is_none_or(|min| value > min || !min_exclusive && value == min);
+                let below = max.is_none_or(|max| value < max || !max_exclusive && value == max);
+                above && below
+            }
+            _ => true,
+        }
+    }
+
+    fn problem(&self) -> String {
+        match *self {
+            Constraint::Range {
+                min: Some(0.0),
+                min_exclusive,
+                max: None,
+                ..
+            } => if min_exclusive {
+                "must be positive"
+            } else {
+                "must be non-negative"
+            }
+            .to_owned(),
+            Constraint::Range {
+                min: Some(min),
+                min_exclusive: false,
+                max: Some(max),
+                max_exclusive: false,
+            } => format!("must be between {} and {}", min, max),
+            Constraint::Range {
+                min,
+                min_exclusive,
+                max,
+                max_exclusive,
+            } => {
+                let lower = min.map(|min| {
+                    let bound = if min_exclusive {
+                        "greater than"
+                    } else {
+                        "at least"
+                    };
+                    format!("{} {}", bound, min)
+                });
+                let upper = max.map(|max| {
+                    let bound = if max_exclusive {
+                        "less than"
+                    } else {
+                        "at most"
+                    };
+                    format!("{} {}", bound, max)
+                });
+                let bounds = lower.into_iter().chain(upper).collect::<Vec<_>>();
+                format!("must be {}", bounds.join(" and "))
+            }
+            Constraint::LessThan { field } => format!("must be less than {}", field),
+            Constraint::AtMost { field } => format!("must not exceed {}", field),
+            Constraint::MinLength { length: 1 } => "must not be empty".to_owned(),
+            Constraint::MinLength { length } => format!("must have at least {} elements", length),
+            Constraint::PositiveSum => "must have a positive sum".to_owned(),
+            Constraint::Custom { description } => description.to_owned(),
+        }
+    }
+}
+
+/// A constraint on one field and whether the request satisfies it.
+struct Rule {
+    field: &'static str,
+    constraint: Constraint,
+    valid: bool,
+}
+
+impl Rule {
+    fn each(
+        field: &'static str,
+        constraint: Constraint,
+        values: impl IntoIterator<Item = f64>,
+    ) -> Self {
+        let valid = values.into_iter().all(|value| constraint.holds(value));
+        Rule {
+            field,
+            constraint,
+            valid,
+        }
+    }
+
+    fn compare(field: &'static str, value: f64, constraint: Constraint, other: f64) -> Self {
+        let valid = match constraint {
+            Constraint::LessThan { .. } => value < other,
+            Constraint::AtMost { .. } => value <= other,
+            _ => constraint.holds(value),
+        };
+        Rule {
+            field,
+            constraint,
+            valid,
+        }
+    }
+
+    fn length(field: &'static str, length: usize, min: usize) -> Self {
+        Rule {
+            field,
+            constraint: Constraint::MinLength { length: min },
+            valid: length >= min,
+        }
+    }
+
+    /// Checks for a positive sum when there are values, leaving an empty list
+    /// to the length rule.
+    fn sum(field: &'static str, values: impl Iterator<Item = f64>) -> Self {
+        let mut values = values.peekable();
+        let valid = values.peek().is_none() || values.sum::<f64>() > 0.0;
+        Rule {
+            field,
+            constraint: Constraint::PositiveSum,
+            valid,
+        }
+    }
+
+    fn custom(field: &'static str, description: &'static str, valid: bool) -> Self {
+        Rule {
+            field,
+            constraint: Constraint::Custom { description },
+            valid,
+        }
+    }
+}
+
+/// One invalid parameter and what is wrong with it.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct FieldError {
+    field: String,
+    problem: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct InvalidParameters(pub(crate) Vec<FieldError>);
+
+impl Display for InvalidParameters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problems = self
+            .0
+            .iter()
+            .map(|error| format!("{} {}", error.field, error.problem))
+            .collect::<Vec<_>>();
+        write!(f, "{}", problems.join("; "))
+    }
+}
+
+impl std::error::Error for InvalidParameters {}
+
+/// Draws `truncated_normal` gives up after, so a region far out in the tails
+/// fails the request instead of spinning.
+const MAX_TRUNCATION_ATTEMPTS: usize = 10_000;
+
+/// Draws `dirichlet` retries when every gamma draw underflows to zero, which
+/// only alphas far below one make likely.
+const MAX_DIRICHLET_ATTEMPTS: usize = 100;
+
+pub(crate) fn sample<R: Rng + ?Sized>(request: &RngRequest, rng: &mut R) -> Result<Sample, Error> {
+    let value = match *request {
+        RngRequest::Uniform(UniformRange::Integer(ref range)) => {
+            rng.sample(Uniform::from(range.clone())) as f64
+        }
+        RngRequest::Uniform(UniformRange::Real { start, end }) => {
+            rng.sample(Uniform::new(start, end))
+        }
+        RngRequest::Normal { mean, std_dev } => rng.sample(Normal::new(mean, std_dev)),
+        RngRequest::Bernoulli { p } => rng.sample(Bernoulli::new(p)) as i8 as f64,
+        RngRequest::Poisson { lambda } => rng.sample(Poisson::new(lambda)) as f64,
+        RngRequest::Exponential { lambda } => rng.sample(Exp::new(lambda)),
+        RngRequest::Gamma { shape, scale } => rng.sample(Gamma::new(shape, scale)),
+        RngRequest::Beta { alpha, beta } => rng.sample(Beta::new(alpha, beta)),
+        RngRequest::LogNormal { mean, std_dev } => rng.sample(LogNormal::new(mean, std_dev)),
+        RngRequest::Weibull { scale, shape } => rng.sample(Weibull::new(scale, shape)),
+        RngRequest::Hypergeometric {
+            population,
+            successes,
+            draws,
+        } => hypergeometric(rng, population, successes, draws) as f64,
+        RngRequest::Dirichlet { ref alpha } => {
+            let values = (0..MAX_DIRICHLET_ATTEMPTS)
+                .map(|_| {
+                    alpha
+                        .iter()
+                        .map(|&alpha| rng.sample(Gamma::new(alpha, 1.0)))
+                        .collect::<Vec<f64>>()
+                })
+                .find(|values| values.iter().sum::<f64>() > 0.0)
+                .ok_or(SamplingFailed {
+                    distribution: request.name(),
+                    attempts: MAX_DIRICHLET_ATTEMPTS,
+                })?;
+            let sum = values.iter().sum::<f64>();
+            return Ok(Sample::Vector(
+                values.into_iter().map(|value| value / sum).collect(),
+            ));
+        }
+        RngRequest::NegativeBinomial { p: 1.0, .. } => 0.0,
+        RngRequest::NegativeBinomial { r, p } => {
+            let rate = rng.sample(Gamma::new(r, (1.0 - p) / p));
+            if rate > 0.0 {
+                rng.sample(Poisson::new(rate)) as f64
+            } else {
+                0.0
+            }
+        }
+        RngRequest::TruncatedNormal {
+            mean,
+            std_dev,
+            low,
+            high,
+        } => {
+            let normal = Normal::new(mean, std_dev);
+            (0..MAX_TRUNCATION_ATTEMPTS)
+                .map(|_| rng.sample(normal))
+                .find(|value| (low..=high).contains(value))
+                .ok_or(SamplingFailed {
+                    distribution: request.name(),
+                    attempts: MAX_TRUNCATION_ATTEMPTS,
+                })?
+        }
+        RngRequest::Mixture { ref components } => {
+            let weights = WeightedIndex::new(components.iter().map(|component| component.weight))?;
+            return sample(&components[rng.sample(weights)].request, rng);
+        }
+        RngRequest::MultivariateNormal {
+            ref mean,
+            ref cov,
+            ref lower,
+        } => {
+            let lower = lower
+                .get_or_init(|| stats::cholesky(cov))
+                .as_ref()
+                .ok_or_else(|| format_err!("cov must be symmetric positive semi-definite"))?;
+            let normals = mean
+                .iter()
+                .map(|_| rng.sample(StandardNormal))
+                .collect::<Vec<f64>>();
+            let values = lower
+                .iter()
+                .zip(mean)
+                .map(|(row, mean)| mean + row.iter().zip(&normals).map(|(l, z)| l * z).sum::<f64>())
+                .collect();
+            return Ok(Sample::Vector(values));
+        }
+        RngRequest::Empirical { ref data } => *data.choose(rng).unwrap(),
+        RngRequest::WeightedChoice { ref weights } => weighted_choice(weights, rng.gen()) as f64,
+        RngRequest::PiecewiseLinear { ref points } => piecewise_linear_quantile(points, rng.gen()),
+        RngRequest::AR1 { phi, sigma, n } => {
+            let mut x = rng.sample(StandardNormal) * sigma / (1.0 - phi * phi).sqrt();
+            let mut values = Vec::with_capacity(n as usize);
+            for _ in 0..n {
+                values.push(x);
+                x = phi * x + sigma * rng.sample(StandardNormal);
+            }
+            return Ok(Sample::Vector(values));
+        }
+        RngRequest::RandomDate {
+            ref start,
+            ref end,
+            output,
+            ref weekdays,
+            ref hours,
+        } => {
+            let bounds = parse_timestamp(start).zip(parse_timestamp(end));
+            let (start, end) = bounds.ok_or_else(|| format_err!("invalid random_date bounds"))?;
+            let (start, end) = (start.timestamp_millis(), end.timestamp_millis());
+            let windows = DateWindows::new(weekdays, hours)
+                .ok_or_else(|| format_err!("invalid random_date weekdays or hours"))?;
+            let count = windows.count(start, end);
+            if count <= 0 {
+                bail!("no time between start and end is in the weekdays' hours");
+            }
+            let millis = windows.nth(start, end, rng.gen_range(0, count));
+            return Ok(match output {
+                DateOutput::Rfc3339 => {
+                    let date = DateTime::<Utc>::from_timestamp_millis(millis)
+                        .ok_or_else(|| format_err!("timestamp {} ms is out of range", millis))?;
+                    Sample::Text(date.to_rfc3339_opts(SecondsFormat::Millis, true))
+                }
+                DateOutput::EpochSeconds => Sample::Scalar(millis.div_euclid(1000) as f64),
+                DateOutput::EpochMillis => Sample::Scalar(millis as f64),
+            });
+        }
+        RngRequest::Categorical {
+            ref items,
+            ref weights,
+        } => {
+            let item = match *weights {
+                Some(ref weights) => &items[rng.sample(WeightedIndex::new(weights)?)],
+                None => items.choose(rng).unwrap(),
+            };
+            return Ok(match *item {
+                Category::Number(value) => Sample::Scalar(value),
+                Category::Text(ref text) => Sample::Text(text.clone()),
+            });
+        }
+        RngRequest::UnitSphere { dimensions } => loop {
+            // Normals are rotation invariant, so their direction is uniform.
+            let normals = (0..dimensions)
+                .map(|_| rng.sample(StandardNormal))
+                .collect::<Vec<f64>>();
+            let norm = normals.iter().map(|x| x * x).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                return Ok(Sample::Vector(normals.iter().map(|x| x / norm).collect()));
+            }
+        },
+    };
+    Ok(Sample::Scalar(value))
+}
+
+/// The index `weighted_choice` picks for a uniform `u` in [0, 1): the first
+/// whose cumulative weight exceeds `u` times the total, so equal weights
+/// split [0, 1) in index order and the same `u` always gives the same index.
+fn weighted_choice(weights: &[f64], u: f64) -> usize {
+    let target = u * weights.iter().sum::<f64>();
+    let mut cumulative = 0.0;
+    weights
+        .iter()
+        .position(|weight| {
+            cumulative += weight;
+            cumulative > target
+        })
+        .unwrap_or_else(|| weights.iter().rposition(|&weight| weight > 0.0).unwrap())
+}
+
+fn parse_timestamp(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+            Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+        })
+}
+
+fn trapezoid_areas(points: &[(f64, f64)]) -> Vec<f64> {
+    points
+        .windows(2)
+        .map(|segment| (segment[1].0 - segment[0].0) * (segment[0].1 + segment[1].1) / 2.0)
+        .collect()
+}
+
+/// Inverts the cumulative trapezoidal integral of `piecewise_linear` points:
+/// finds the segment holding `p` of the area, then solves the quadratic for
+/// the remaining area within it.
+fn piecewise_linear_quantile(points: &[(f64, f64)], p: f64) -> f64 {
+    let areas = trapezoid_areas(points);
+    let mut target = p * areas.iter().sum::<f64>();
+    for (&area, segment) in areas.iter().zip(points.windows(2)) {
+        let ((x0, d0), (x1, d1)) = (segment[0], segment[1]);
+        if target > area || area == 0.0 {
+            target -= area;
+            continue;
+        }
+        // Area from x0 to x0 + t is d0 t + slope t^2 / 2; this root avoids
+        // cancelling when the slope is near zero.
+        let slope = (d1 - d0) / (x1 - x0);
+        let root = d0 + (d0 * d0 + 2.0 * slope * target).max(0.0).sqrt();
+        let t = if root > 0.0 { 2.0 * target / root } else { 0.0 };
+        return (x0 + t).min(x1);
+    }
+    points.last().unwrap().0
+}
+
+pub(crate) fn hypergeometric<R: Rng + ?Sized>(
+    rng: &mut R,
+    population: u64,
+    successes: u64,
+    draws: u64,
+) -> u64 {
+    if draws > population / 2 {
+        return successes - hypergeometric(rng, population, successes, population - draws);
+    }
+    let (mut population, mut successes) = (population, successes);
+    let mut drawn = 0;
+    for _ in 0..draws {
+        if rng.gen_range(0, population) < successes {
+            successes -= 1;
+            drawn += 1;
+        }
+        population -= 1;
+    }
+    drawn
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+    use serde_json::json;
+
+    fn request(distribution: &str, parameters: Value) -> RngRequest {
+        let request = json!({"distribution": distribution, "parameters": parameters});
+        serde_json::from_value(request).unwrap()
+    }
+
+    fn draws(request: &RngRequest, count: usize) -> Vec<Sample> {
+        let mut rng = StdRng::seed_from_u64(7);
+        (0..count)
+            .map(|_| sample(request, &mut rng).unwrap())
+            .collect()
+    }
+
+    fn scalar(sample: &Sample) -> f64 {
+        match *sample {
+            Sample::Scalar(value) => value,
+            _ => panic!("expected a scalar"),
+        }
+    }
+
+    fn vector(sample: &Sample) -> &[f64] {
+        match *sample {
+            Sample::Vector(ref values) => values,
+            _ => panic!("expected a vector"),
+        }
+    }
+
+    fn is_count(value: f64) -> bool {
+        value >= 0.0 && value.fract() == 0.0
+    }
+
+    /// Whether `sample` is a value `request` can produce.
+    fn in_support(request: &RngRequest, sample: &Sample) -> bool {
+        match *request {
+            RngRequest::Uniform(UniformRange::Integer(ref range)) => {
+                let value = scalar(sample);
+                value.fract() == 0.0 && (range.start..range.end).contains(&(value as i32))
+            }
+            RngRequest::Uniform(UniformRange::Real { start, end }) => {
+                (start..end).contains(&scalar(sample))
+            }
+            RngRequest::Normal { .. } | RngRequest::Mixture { .. } => scalar(sample).is_finite(),
+            RngRequest::Bernoulli { .. } => [0.0, 1.0].contains(&scalar(sample)),
+            RngRequest::Poisson { .. } | RngRequest::NegativeBinomial { .. } => {
+                is_count(scalar(sample))
+            }
+            RngRequest::Exponential { .. } | RngRequest::Weibull { .. } => scalar(sample) >= 0.0,
+            RngRequest::Gamma { .. } | RngRequest::LogNormal { .. } => scalar(sample) > 0.0,
+            RngRequest::Beta { .. } => (0.0..=1.0).contains(&scalar(sample)),
+            RngRequest::Hypergeometric {
+                population,
+                successes,
+                draws,
+            } => {
+                let value = scalar(sample);
+                let least = draws.saturating_sub(population - successes) as f64;
+                is_count(value) && value >= least && value <= draws.min(successes) as f64
+            }
+            RngRequest::Dirichlet { ref alpha } => {
+                let values = vector(sample);
+                values.len() == alpha.len() && values.iter().all(|x| (0.0..=1.0).contains(x))
+            }
+            RngRequest::TruncatedNormal { low, high, .. } => (low..=high).contains(&scalar(sample)),
+            RngRequest::MultivariateNormal { ref mean, .. } => {
+                let values = vector(sample);
+                values.len() == mean.len() && values.iter().all(|x| x.is_finite())
+            }
+            RngRequest::Empirical { ref data } => data.contains(&scalar(sample)),
+            RngRequest::UnitSphere { dimensions } => vector(sample).len() == dimensions as usize,
+            RngRequest::WeightedChoice { ref weights } => {
+                let value = scalar(sample);
+                is_count(value) && (value as usize) < weights.len()
+            }
+            RngRequest::PiecewiseLinear { ref points } => {
+                let value = scalar(sample);
+                (points[0].0..=points[points.len() - 1].0).contains(&value)
+            }
+            RngRequest::AR1 { n, .. } => vector(sample).len() == n as usize,
+            RngRequest::RandomDate {
+                ref start,
+                ref end,
+                ref weekdays,
+                ref hours,
+                ..
+            } => {
+                let date = match *sample {
+                    Sample::Text(ref text) => parse_timestamp(text).unwrap(),
+                    _ => return false,
+                };
+                let weekday = date.format("%a").to_string().to_lowercase();
+                (parse_timestamp(start).unwrap()..=parse_timestamp(end).unwrap()).contains(&date)
+                    && weekdays.contains(&weekday)
+                    && hours.contains(&date.hour())
+            }
+            RngRequest::Categorical { ref items, .. } => {
+                items.iter().any(|item| match (item, sample) {
+                    (Category::Number(item), Sample::Scalar(value)) => item == value,
+                    (Category::Text(item), Sample::Text(value)) => item == value,
+                    _ => false,
+                })
+            }
+        }
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        for request in RngRequest::defaults() {
+            assert!(request.validate().is_ok(), "{}", request.name());
+        }
+    }
+
+    #[test]
+    fn samples_stay_in_support() {
+        for request in RngRequest::defaults() {
+            for value in draws(&request, 500) {
+                assert!(
+                    in_support(&request, &value),
+                    "{} drew {}",
+                    request.name(),
+                    value.text()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_reproducible_from_a_seed() {
+        for request in RngRequest::defaults() {
+            let first = draws(&request, 20)
+                .iter()
+                .map(Text::text)
+                .collect::<Vec<_>>();
+            let second = draws(&request, 20)
+                .iter()
+                .map(Text::text)
+                .collect::<Vec<_>>();
+            assert_eq!(first, second, "{}", request.name());
+        }
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let cases = [
+            ("uniform", json!({"start": 5, "end": 5}), "start"),
+            ("uniform", json!({"start": 1.5, "end": 0.5}), "start"),
+            ("normal", json!({"std_dev": -1.0}), "std_dev"),
+            ("bernoulli", json!({"p": 1.5}), "p"),
+            ("poisson", json!({"lambda": 0.0}), "lambda"),
+            ("exponential", json!({"lambda": -1.0}), "lambda"),
+            ("gamma", json!({"shape": 0.0, "scale": 1.0}), "shape"),
+            ("beta", json!({"alpha": 1.0, "beta": 0.0}), "beta"),
+            ("log_normal", json!({"std_dev": -0.5}), "std_dev"),
+            ("weibull", json!({"scale": 0.0, "shape": 1.0}), "scale"),
+            (
+                "hypergeometric",
+                json!({"population": 10, "successes": 11, "draws": 3}),
+                "successes",
+            ),
+            (
+                "hypergeometric",
+                json!({"population": 10, "successes": 5, "draws": 11}),
+                "draws",
+            ),
+            ("dirichlet", json!({"alpha": []}), "alpha"),
+            ("dirichlet", json!({"alpha": [1.0, 0.0]}), "alpha"),
+            ("negative_binomial", json!({"r": 0.0, "p": 0.5}), "r"),
+            ("negative_binomial", json!({"r": 1.0, "p": 0.0}), "p"),
+            ("truncated_normal", json!({"low": 1.0, "high": -1.0}), "low"),
+            ("mixture", json!({"components": []}), "components"),
+            (
+                "mixture",
+                json!({"components": [{
+                    "weight": -1.0,
+                    "request": {"distribution": "normal", "parameters": {}},
+                }]}),
+                "components[].weight",
+            ),
+            (
+                "mixture",
+                json!({"components": [{
+                    "weight": 1.0,
+                    "request": {"distribution": "normal", "parameters": {"std_dev": -1.0}},
+                }]}),
+                "components[0].request.parameters.std_dev",
+            ),
+            (
+                "multivariate_normal",
+                json!({"mean": [0.0, 0.0], "cov": [[1.0]]}),
+                "cov",
+            ),
+            (
+                "multivariate_normal",
+                json!({"mean": [0.0, 0.0], "cov": [[1.0, 2.0], [2.0, 1.0]]}),
+                "cov",
+            ),
+            ("empirical", json!({"data": []}), "data"),
+            ("unit_sphere", json!({"dimensions": 0}), "dimensions"),
+            ("weighted_choice", json!({"weights": [0.0, 0.0]}), "weights"),
+            (
+                "weighted_choice",
+                json!({"weights": [1.0, -1.0]}),
+                "weights",
+            ),
+            (
+                "piecewise_linear",
+                json!({"points": [[1.0, 1.0], [0.0, 1.0]]}),
+                "points",
+            ),
+            (
+                "piecewise_linear",
+                json!({"points": [[0.0, 0.0], [1.0, 0.0]]}),
+                "points",
+            ),
+            ("ar1", json!({"phi": 1.0, "sigma": 1.0, "n": 10}), "phi"),
+            ("ar1", json!({"phi": 0.5, "sigma": 1.0, "n": 0}), "n"),
+            (
+                "random_date",
+                json!({"start": "yesterday", "end": "2000-01-01"}),
+                "start",
+            ),
+            (
+                "random_date",
+                json!({"start": "2000-01-02", "end": "2000-01-01"}),
+                "start",
+            ),
+            (
+                "random_date",
+                json!({"start": "2000-01-01", "end": "2000-01-02", "weekdays": ["someday"]}),
+                "weekdays",
+            ),
+            (
+                "random_date",
+                json!({"start": "2000-01-01", "end": "2000-01-02", "hours": {"start": 9, "end": 25}}),
+                "hours",
+            ),
+            ("categorical", json!({"items": []}), "items"),
+            (
+                "categorical",
+                json!({"items": ["a", "b"], "weights": [1.0]}),
+                "weights",
+            ),
+        ];
+        for (distribution, parameters, field) in cases.iter() {
+            let problems = request(distribution, parameters.clone()).problems();
+            assert!(
+                problems.iter().any(|problem| problem.field == *field),
+                "{} {} should have flagged {}",
+                distribution,
+                parameters,
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn dirichlet_components_sum_to_one() {
+        // Gamma draws for an alpha of 1e-3 underflow to zero about half the
+        // time, so both components often do at once.
+        for alpha in [vec![1.0, 1.0, 1.0], vec![0.1, 5.0], vec![1e-3, 1e-3]] {
+            let request = RngRequest::Dirichlet { alpha };
+            for value in draws(&request, 1000) {
+                let sum = vector(&value).iter().sum::<f64>();
+                assert!((sum - 1.0).abs() < 1e-9, "sum {}", sum);
+            }
+        }
+    }
+
+    #[test]
+    fn dirichlet_fails_when_every_draw_underflows() {
+        let request = RngRequest::Dirichlet {
+            alpha: vec![1e-300, 1e-300],
+        };
+        match sample(&request, &mut StdRng::seed_from_u64(7)) {
+            Err(err) => assert!(err.downcast_ref::<SamplingFailed>().is_some()),
+            Ok(value) => panic!("drew {}", value.text()),
+        }
+    }
+
+    #[test]
+    fn negative_binomial_mean_matches() {
+        for &(r, p) in &[(3.0, 0.4), (0.5, 0.8), (10.0, 0.1)] {
+            let request = RngRequest::NegativeBinomial { r, p };
+            let count = 20_000;
+            let mean = draws(&request, count).iter().map(scalar).sum::<f64>() / count as f64;
+            // Within four standard errors of r(1 - p) / p.
+            let expected = r * (1.0 - p) / p;
+            let tolerance = 4.0 * (expected / p / count as f64).sqrt();
+            assert!(
+                (mean - expected).abs() < tolerance,
+                "r {} p {}: mean {}, expected {}",
+                r,
+                p,
+                mean,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_normal_stays_within_bounds() {
+        // The last interval is out in the upper tail, where about one draw
+        // in 750 lands.
+        for &(low, high) in &[(-1.0, 1.0), (0.5, 0.6), (-10.0, 0.0), (3.0, 4.0)] {
+            let request = RngRequest::TruncatedNormal {
+                mean: 0.0,
+                std_dev: 1.0,
+                low,
+                high,
+            };
+            for value in draws(&request, 500) {
+                let value = scalar(&value);
+                assert!(
+                    (low..=high).contains(&value),
+                    "{} outside [{}, {}]",
+                    value,
+                    low,
+                    high
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unit_sphere_vectors_have_unit_norm() {
+        for &dimensions in &[1, 2, 3, 50] {
+            let request = RngRequest::UnitSphere { dimensions };
+            for value in draws(&request, 200) {
+                let norm = vector(&value).iter().map(|x| x * x).sum::<f64>().sqrt();
+                assert!((norm - 1.0).abs() < 1e-12, "norm {}", norm);
+            }
+        }
+    }
+
+    #[test]
+    fn wide_integer_uniform_ranges_do_not_overflow() {
+        let request = RngRequest::Uniform(UniformRange::Integer(i32::MIN..i32::MAX));
+        assert_eq!(request.quantile(0.0), Some(f64::from(i32::MIN)));
+        assert_eq!(request.quantile(0.5), Some(-1.0));
+        assert_eq!(request.quantile(1.0), Some(f64::from(i32::MAX) - 1.0));
+        let n = f64::from(u32::MAX);
+        assert_eq!(request.cdf(f64::from(i32::MIN)), Some(1.0 / n));
+        assert_eq!(request.cdf(f64::from(i32::MAX) - 1.0), Some(1.0));
+        let inverse = request.inverse_transform().unwrap();
+        assert_eq!(inverse(0.0), f64::from(i32::MIN));
+        assert_eq!(inverse(0.999_999_999_9), f64::from(i32::MAX) - 1.0);
+    }
+
+    #[test]
+    fn hypergeometric_takes_the_complement_of_large_draws() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            assert_eq!(hypergeometric(&mut rng, 10, 4, 10), 4);
+            assert_eq!(hypergeometric(&mut rng, 10, 4, 0), 0);
+        }
+    }
+
+    #[test]
+    fn weighted_choice_is_reproducible_from_a_seed() {
+        let state = State::new(Config::default()).unwrap();
+        let query = json!({"seed": "42"});
+        let options = RandomOptions::parse(&query, &HeaderMap::new(), &state.config(), false);
+        let options = options.unwrap();
+        let request = RngRequest::WeightedChoice {
+            weights: vec![1.0, 1.0, 1.0, 1.0],
+        };
+        let draw = || {
+            handle_request(&request, &options, &state)
+                .unwrap()
+                .value
+                .text()
+        };
+        assert_eq!(draw(), draw());
+        // As documented, the index is `weighted_choice` of the seeded
+        // generator's first f64.
+        let u = StdRng::seed_from_u64(42).gen::<f64>();
+        let expected = weighted_choice(&[1.0, 1.0, 1.0, 1.0], u);
+        assert_eq!(draw(), (expected as f64).text());
+    }
+
+    #[test]
+    fn weighted_choice_splits_the_unit_interval_by_weight() {
+        let weights = [1.0, 0.0, 3.0];
+        assert_eq!(weighted_choice(&weights, 0.0), 0);
+        assert_eq!(weighted_choice(&weights, 0.24), 0);
+        assert_eq!(weighted_choice(&weights, 0.25), 2);
+        assert_eq!(weighted_choice(&weights, 0.99), 2);
+    }
+}
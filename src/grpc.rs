@@ -0,0 +1,434 @@
+//! The gRPC interface described by `proto/random.proto`, served on its own
+//! listener over cleartext HTTP/2 with code `build.rs` generates from the
+//! proto. Distribution requests go through the same parsing, validation and
+//! sampling as `/random`.
+
+use super::*;
+use tonic::transport::server::{Connected, TcpConnectInfo};
+use tonic::{Code, Status};
+
+mod proto {
+    tonic::include_proto!("random.v1");
+}
+
+use proto::random_server::{Random, RandomServer};
+use proto::{BatchReply, BatchRequest, BytesReply, BytesRequest, SampleRequest};
+use proto::{UuidReply, UuidRequest};
+
+impl Connected for Connection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> TcpConnectInfo {
+        TcpConnectInfo {
+            local_addr: None,
+            remote_addr: Some(self.remote_addr()),
+        }
+    }
+}
+
+fn value(sample: &Sample) -> proto::Value {
+    let value = match *sample {
+        Sample::Scalar(scalar) => proto::value::Value::Scalar(scalar),
+        Sample::Vector(ref values) => proto::value::Value::Vector(proto::Vector {
+            values: values.clone(),
+        }),
+        Sample::Text(ref text) => proto::value::Value::Text(text.clone()),
+    };
+    proto::Value { value: Some(value) }
+}
+
+/// The request's `RngRequest` and the options its `seed` and `count` give,
+/// checked like the equivalent `/random` query.
+fn parse_rng_request(
+    request: &str,
+    seed: Option<u64>,
+    count: Option<u64>,
+    headers: &HeaderMap,
+    state: &State,
+) -> Result<(RngRequest, RandomOptions), Error> {
+    if request.is_empty() {
+        bail!("missing request");
+    }
+    let request = parse_request(request.as_bytes(), &state.config())?;
+    let mut query = serde_json::Map::new();
+    if let Some(seed) = seed {
+        query.insert("seed".to_owned(), seed.to_string().into());
+    }
+    if let Some(count) = count {
+        query.insert("count".to_owned(), count.to_string().into());
+    }
+    let options = RandomOptions::parse(&Value::Object(query), headers, &state.config(), false)?;
+    Ok((request, options))
+}
+
+fn sample_value(
+    message: SampleRequest,
+    headers: &HeaderMap,
+    state: &State,
+    remote: &SocketAddr,
+) -> Result<proto::Value, Error> {
+    let (request, options) =
+        parse_rng_request(&message.request, message.seed, None, headers, state)?;
+    state.check_distribution_limit(remote, &request)?;
+    let resp = handle_request(&request, &options, state)?;
+    options.check(&request, &resp.value)?;
+    state.audit(&options.request_id, &request, &resp.value);
+    Ok(value(&resp.value))
+}
+
+fn batch_values(
+    message: BatchRequest,
+    headers: &HeaderMap,
+    state: &State,
+    remote: &SocketAddr,
+) -> Result<BatchReply, Error> {
+    let count = u64::from(message.count);
+    let (request, options) =
+        parse_rng_request(&message.request, message.seed, Some(count), headers, state)?;
+    state.check_distribution_limit(remote, &request)?;
+    let values = batch(&request, &options, count as usize, state)?;
+    state.audit(&options.request_id, &request, &values);
+    Ok(BatchReply {
+        values: values.iter().map(value).collect(),
+    })
+}
+
+fn os_bytes(message: BytesRequest, state: &State) -> Result<BytesReply, Error> {
+    let max = state.config().max_bytes_len;
+    let len = match message.len {
+        0 => 32,
+        len => len as usize,
+    };
+    if len > max {
+        bail!("len must be between 1 and {}, got {}", max, len);
+    }
+    let mut bytes = vec![0; len];
+    OsRng::new()?.fill_bytes(&mut bytes);
+    Ok(BytesReply { value: bytes })
+}
+
+fn uuids(message: UuidRequest, state: &State) -> Result<UuidReply, Error> {
+    let kind = match message.version {
+        0 | 4 => IdKind::UuidV4,
+        7 => IdKind::UuidV7,
+        version => bail!("unsupported uuid version {}, expected 4 or 7", version),
+    };
+    let count = message.count.max(1) as usize;
+    if count > state.config().max_count {
+        bail!(
+            "count {} exceeds the maximum of {}",
+            count,
+            state.config().max_count
+        );
+    }
+    let values = state.with_rng(EntropySource::OsRng, |rng| {
+        (0..count).map(|_| kind.generate(rng)).collect()
+    })?;
+    Ok(UuidReply { values })
+}
+
+fn status((code, message): (Code, String)) -> Status {
+    Status::new(code, message)
+}
+
+/// The status the access log records for a call failing with `code`, that
+/// of the equivalent HTTP request.
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::InvalidArgument => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+struct RandomService {
+    state: Arc<State>,
+}
+
+impl RandomService {
+    /// Authenticates and rate limits a call like an HTTP request, answers it
+    /// with `f` and logs it. Failures are the code and message of the call's
+    /// `Status`.
+    fn call<M, R>(
+        &self,
+        method: &str,
+        request: tonic::Request<M>,
+        f: impl FnOnce(M, &HeaderMap, &State, &SocketAddr) -> Result<R, Error>,
+    ) -> Result<tonic::Response<R>, (Code, String)> {
+        let start = Instant::now();
+        let state = &self.state;
+        let remote = request
+            .remote_addr()
+            .unwrap_or_else(|| ([127, 0, 0, 1], 0).into());
+        let mut headers = request.metadata().clone().into_headers();
+        let id = request_id(&headers);
+        headers.insert("x-request-id", id.clone());
+        let res = state
+            .authenticate(&headers)
+            .map_err(|(rejection, message)| match rejection {
+                Rejection::Unauthorized => (Code::Unauthenticated, message.to_owned()),
+                _ => (Code::PermissionDenied, message.to_owned()),
+            })
+            .and_then(|()| {
+                state
+                    .check_rate_limit(&remote, &headers)
+                    .map_err(|limited| (Code::ResourceExhausted, limited.to_string()))
+            })
+            .and_then(|()| {
+                f(request.into_inner(), &headers, state, &remote).map_err(|err| {
+                    match err.downcast_ref::<RateLimited>() {
+                        Some(_) => (Code::ResourceExhausted, err.to_string()),
+                        None => (Code::InvalidArgument, err.to_string()),
+                    }
+                })
+            });
+        let path = format!("/random.v1.Random/{}", method);
+        let id = id.to_str().unwrap();
+        let code = match res {
+            Ok(_) => StatusCode::OK,
+            Err((code, _)) => http_status(code),
+        };
+        state.log_access(id, &remote, &Method::POST, &path, code, start.elapsed());
+        let reply =
+            res.inspect_err(|(_, message)| debug!("gRPC call {} failed: {}", path, message))?;
+        let mut resp = tonic::Response::new(reply);
+        resp.metadata_mut()
+            .insert("x-request-id", id.parse().unwrap());
+        Ok(resp)
+    }
+}
+
+#[tonic::async_trait]
+impl Random for RandomService {
+    async fn sample(
+        &self,
+        request: tonic::Request<SampleRequest>,
+    ) -> Result<tonic::Response<proto::Value>, Status> {
+        self.call("Sample", request, sample_value).map_err(status)
+    }
+
+    async fn batch(
+        &self,
+        request: tonic::Request<BatchRequest>,
+    ) -> Result<tonic::Response<BatchReply>, Status> {
+        self.call("Batch", request, batch_values).map_err(status)
+    }
+
+    async fn bytes(
+        &self,
+        request: tonic::Request<BytesRequest>,
+    ) -> Result<tonic::Response<BytesReply>, Status> {
+        self.call("Bytes", request, |message, _, state, _| {
+            os_bytes(message, state)
+        })
+        .map_err(status)
+    }
+
+    async fn uuid(
+        &self,
+        request: tonic::Request<UuidRequest>,
+    ) -> Result<tonic::Response<UuidReply>, Status> {
+        self.call("Uuid", request, |message, _, state, _| {
+            uuids(message, state)
+        })
+        .map_err(status)
+    }
+}
+
+/// Accepts gRPC connections on `listener` until the runtime shuts down.
+pub(crate) async fn serve(state: Arc<State>, listener: TcpListener) {
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    let write_timeout = state.config().write_timeout_secs.map(Duration::from_secs);
+    let incoming = stream::unfold(listener, |listener| async {
+        let stream = listener.accept().await.map(|(stream, _)| stream);
+        Some((stream, listener))
+    })
+    .filter_map(move |stream| async move {
+        stream
+            .and_then(|stream| Connection::tcp(stream, write_timeout))
+            .map_err(|err| warn!("Cannot accept gRPC connection: {}", err))
+            .ok()
+    })
+    .map(Ok::<_, io::Error>);
+    // Read once, so `/admin/reload` leaves the gRPC message limit alone.
+    let limit = state.config().max_body_bytes;
+    let service = RandomServer::new(RandomService { state }).max_decoding_message_size(limit);
+    let server = tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_incoming(incoming);
+    if let Err(err) = server.await {
+        warn!("gRPC server error: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::codec::ProstCodec;
+    use tonic::transport::Channel;
+
+    /// Serves `config` on an ephemeral port and connects to it.
+    async fn connect(config: Config) -> Channel {
+        connect_state(Arc::new(State::new(config).unwrap())).await
+    }
+
+    async fn connect_state(state: Arc<State>) -> Channel {
+        let listener = bind(&"127.0.0.1:0".parse().unwrap(), false, false).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve(state, listener));
+        let endpoint = Channel::from_shared(format!("http://127.0.0.1:{}", port)).unwrap();
+        endpoint.connect().await.unwrap()
+    }
+
+    async fn call<M, R>(
+        channel: &Channel,
+        request: tonic::Request<M>,
+        method: &str,
+    ) -> Result<R, Status>
+    where
+        M: prost::Message + Send + 'static,
+        R: prost::Message + Default + Send + 'static,
+    {
+        let mut grpc = tonic::client::Grpc::new(channel.clone());
+        grpc.ready().await.unwrap();
+        let path = format!("/random.v1.Random/{}", method).parse().unwrap();
+        let reply = grpc
+            .unary(request, path, ProstCodec::<M, R>::default())
+            .await?;
+        Ok(reply.into_inner())
+    }
+
+    fn sample_request(request: &str, seed: Option<u64>) -> tonic::Request<SampleRequest> {
+        tonic::Request::new(SampleRequest {
+            request: request.to_owned(),
+            seed,
+        })
+    }
+
+    #[tokio::test]
+    async fn samples_match_the_http_api() {
+        let channel = connect(Config::default()).await;
+        let uniform = r#"{"distribution": "uniform", "parameters": {"start": 1, "end": 2}}"#;
+        let reply: proto::Value = call(&channel, sample_request(uniform, None), "Sample")
+            .await
+            .unwrap();
+        assert_eq!(reply, value(&Sample::Scalar(1.0)));
+
+        let normal = r#"{"distribution": "normal", "parameters": {}}"#;
+        let draw = || call::<_, proto::Value>(&channel, sample_request(normal, Some(7)), "Sample");
+        assert_eq!(draw().await.unwrap(), draw().await.unwrap());
+
+        let dirichlet = r#"{"distribution": "dirichlet", "parameters": {"alpha": [1, 1]}}"#;
+        let request = tonic::Request::new(BatchRequest {
+            request: dirichlet.to_owned(),
+            count: 3,
+            seed: None,
+        });
+        let reply: BatchReply = call(&channel, request, "Batch").await.unwrap();
+        assert_eq!(reply.values.len(), 3);
+        for value in reply.values {
+            match value.value {
+                Some(proto::value::Value::Vector(vector)) => assert_eq!(vector.values.len(), 2),
+                _ => panic!("expected a vector"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_invalid_arguments() {
+        let channel = connect(Config::default()).await;
+        let beta = r#"{"distribution": "beta", "parameters": {"alpha": -1, "beta": 1}}"#;
+        for request in &["", "{", beta] {
+            let res = call::<_, proto::Value>(&channel, sample_request(request, None), "Sample");
+            assert_eq!(
+                res.await.unwrap_err().code(),
+                Code::InvalidArgument,
+                "{}",
+                request
+            );
+        }
+        let request = tonic::Request::new(UuidRequest {
+            version: 5,
+            count: 1,
+        });
+        let res = call::<_, UuidReply>(&channel, request, "Uuid").await;
+        assert_eq!(res.unwrap_err().code(), Code::InvalidArgument);
+        let request = tonic::Request::new(BytesRequest { len: 1 << 20 });
+        let res = call::<_, BytesReply>(&channel, request, "Bytes").await;
+        assert_eq!(res.unwrap_err().code(), Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn bytes_and_uuids() {
+        let channel = connect(Config::default()).await;
+        let request = tonic::Request::new(BytesRequest { len: 0 });
+        let reply: BytesReply = call(&channel, request, "Bytes").await.unwrap();
+        assert_eq!(reply.value.len(), 32);
+        let request = tonic::Request::new(UuidRequest {
+            version: 7,
+            count: 2,
+        });
+        let reply: UuidReply = call(&channel, request, "Uuid").await.unwrap();
+        assert_eq!(reply.values.len(), 2);
+        assert!(reply
+            .values
+            .iter()
+            .all(|id| id.len() == 36 && &id[14..15] == "7"));
+    }
+
+    #[tokio::test]
+    async fn calls_need_an_api_key_when_keys_are_configured() {
+        let config = Config {
+            api_keys: vec!["secret".to_owned()],
+            ..Config::default()
+        };
+        let channel = connect(config).await;
+        let request = || tonic::Request::new(BytesRequest { len: 4 });
+        let res = call::<_, BytesReply>(&channel, request(), "Bytes").await;
+        assert_eq!(res.unwrap_err().code(), Code::Unauthenticated);
+        let mut request = request();
+        request
+            .metadata_mut()
+            .insert("x-api-key", "secret".parse().unwrap());
+        let reply: BytesReply = call(&channel, request, "Bytes").await.unwrap();
+        assert_eq!(reply.value.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn audit_records_carry_the_returned_request_id() {
+        let path = env::temp_dir().join(format!("grpc-audit-{}.ndjson", process::id()));
+        let config = Config {
+            audit_log_path: Some(path.clone()),
+            ..Config::default()
+        };
+        let state = Arc::new(State::new(config).unwrap());
+        let mut grpc = tonic::client::Grpc::new(connect_state(state.clone()).await);
+        let mut returned = Vec::new();
+        // A space is not allowed in request ids, so the server makes one up.
+        for id in &[Some("client-id"), Some("has space"), None] {
+            let mut request = sample_request(r#"{"distribution": "normal"}"#, None);
+            if let Some(id) = id {
+                let id = id.parse().unwrap();
+                request.metadata_mut().insert("x-request-id", id);
+            }
+            grpc.ready().await.unwrap();
+            let method = "/random.v1.Random/Sample".parse().unwrap();
+            let codec = ProstCodec::<SampleRequest, proto::Value>::default();
+            let reply = grpc.unary(request, method, codec).await.unwrap();
+            let id = reply.metadata().get("x-request-id").unwrap();
+            returned.push(id.to_str().unwrap().to_owned());
+        }
+        assert_eq!(returned[0], "client-id");
+        assert_ne!(returned[1], "has space");
+        state.audit_log.as_ref().unwrap().close();
+        let audited = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let audited = audited
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["request_id"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(audited, returned);
+    }
+}
@@ -0,0 +1,3657 @@
+//! The random number service: `run` is the command line, while `service`,
+//! `handle_request` and the request and response types let another program
+//! embed the routes or generate without HTTP.
+
+extern crate base64;
+extern crate brotli;
+extern crate chrono;
+extern crate flate2;
+extern crate futures;
+extern crate hmac;
+extern crate hyper;
+extern crate net2;
+extern crate prometheus;
+extern crate queryst;
+extern crate rand;
+extern crate rand_chacha;
+extern crate rand_pcg;
+extern crate rmp_serde;
+extern crate rustls_pemfile;
+extern crate serde_cbor;
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_path_to_error;
+extern crate sha2;
+extern crate sysinfo;
+extern crate tokio;
+extern crate tokio_rustls;
+extern crate ulid;
+extern crate uuid;
+
+mod config;
+mod dice;
+mod distributions;
+mod grpc;
+mod openapi;
+mod quasi;
+mod router;
+mod serialization;
+mod stats;
+mod ws;
+#[macro_use]
+extern crate failure;
+
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL};
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc, Weekday};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::Error;
+use flate2::read::GzDecoder;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::{future, stream, FutureExt, Stream, StreamExt};
+use hmac::{Hmac, Mac};
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCESS_CONTROL_ALLOW_HEADERS,
+    ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS,
+    ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_METHOD, AUTHORIZATION, CACHE_CONTROL,
+    CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+    RETRY_AFTER, VARY, WWW_AUTHENTICATE,
+};
+use hyper::server::accept;
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, info, trace, warn, LevelFilter};
+use net2::unix::UnixTcpBuilderExt;
+use net2::TcpBuilder;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use rand::distributions::{
+    Alphanumeric, Bernoulli, Beta, Exp, Gamma, LogNormal, Normal, Poisson, StandardNormal, Uniform,
+    Weibull, WeightedIndex,
+};
+use rand::rngs::{OsRng, StdRng};
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaChaRng;
+use rand_pcg::Pcg64Mcg;
+use serde::Serialize;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::ser::{CompactFormatter, Formatter, PrettyFormatter};
+use serde_json::Value;
+use sha2::Sha256;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::env;
+use std::fmt::{self, Display};
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::hint;
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::ops::Range;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process;
+use std::slice;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock, RwLock};
+use std::task::{self, Poll};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use sysinfo::System;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{self, Sleep};
+use tokio_rustls::rustls::server::{AllowAnyAuthenticatedClient, NoClientAuth};
+use tokio_rustls::rustls::{
+    version, Certificate, PrivateKey, RootCertStore, ServerConfig, ALL_CIPHER_SUITES,
+};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use config::*;
+use distributions::*;
+use serialization::*;
+
+pub use config::{load_config, Config};
+pub use distributions::{Category, DateOutput, MixtureComponent, RngRequest, UniformRange};
+pub use serialization::{serialize, Format, Text};
+
+#[derive(Serialize)]
+pub struct RngResponse {
+    value: Sample,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<ValueType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uniform: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    density: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_ns: Option<u64>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(untagged)]
+pub enum Sample {
+    Scalar(f64),
+    Vector(Vec<f64>),
+    /// A non-numeric value, which numeric options such as `scale` and
+    /// `assert_min` leave alone.
+    Text(String),
+}
+
+impl Sample {
+    fn map(self, mut f: impl FnMut(f64) -> f64) -> Sample {
+        match self {
+            Sample::Scalar(value) => Sample::Scalar(f(value)),
+            Sample::Vector(values) => Sample::Vector(values.into_iter().map(f).collect()),
+            Sample::Text(text) => Sample::Text(text),
+        }
+    }
+
+    fn scalar(&self) -> Option<f64> {
+        match *self {
+            Sample::Scalar(value) => Some(value),
+            Sample::Vector(_) | Sample::Text(_) => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HistogramResponse {
+    edges: Vec<f64>,
+    counts: Vec<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_ns: Option<u64>,
+}
+
+/// The `stats=true` summary of a batch in place of its values. `variance` is
+/// the sample variance, zero for a single value.
+#[derive(Serialize)]
+struct SummaryResponse {
+    count: usize,
+    mean: f64,
+    variance: f64,
+    min: f64,
+    max: f64,
+    histogram: HistogramResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_ns: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct StringResponse {
+    value: String,
+}
+
+#[derive(Serialize)]
+struct StreamInfo {
+    name: String,
+    draws: u64,
+}
+
+#[derive(Serialize)]
+struct StreamsResponse {
+    streams: Vec<StreamInfo>,
+}
+
+#[derive(Serialize)]
+struct VersionResponse {
+    version: &'static str,
+}
+
+#[derive(Serialize)]
+struct DistributionsResponse {
+    distributions: Vec<DistributionInfo>,
+}
+
+#[derive(Serialize)]
+struct DistributionInfo {
+    name: &'static str,
+    value_type: ValueType,
+    parameters: Vec<ParameterInfo>,
+}
+
+#[derive(Serialize)]
+struct ParameterInfo {
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<Value>,
+    constraints: Vec<Constraint>,
+}
+
+#[derive(Serialize)]
+struct WarmupResponse {
+    warmed: Vec<&'static str>,
+}
+
+/// `PUT /admin/loglevel`'s body, and without `seconds` what it answers.
+#[derive(Deserialize, Serialize)]
+struct LogLevel {
+    level: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+    reloaded: bool,
+}
+
+/// A claimed sequence for `POST /verify`: `checksum` is the FNV-1a hash, in
+/// hex, of the body `/random?seed={seed}&count={count}` returns for `request`.
+#[derive(Deserialize)]
+struct VerifyRequest {
+    seed: u64,
+    count: usize,
+    request: Value,
+    checksum: String,
+}
+
+#[derive(Serialize)]
+struct VerifyResponse {
+    verified: bool,
+    checksum: String,
+}
+
+#[derive(Serialize)]
+struct SeedResponse {
+    seed: u64,
+}
+
+#[derive(Serialize)]
+struct FlipResponse {
+    value: bool,
+}
+
+enum Charset {
+    Alphanumeric,
+    Hex,
+    Alpha,
+    Digits,
+    /// Printable ASCII other than space.
+    Ascii,
+    /// The distinct characters of the `chars` parameter.
+    Custom(Vec<char>),
+}
+
+impl FromStr for Charset {
+    type Err = Error;
+
+    fn from_str(charset: &str) -> Result<Self, Error> {
+        match charset {
+            "alphanumeric" => Ok(Charset::Alphanumeric),
+            "hex" => Ok(Charset::Hex),
+            "alpha" => Ok(Charset::Alpha),
+            "digits" => Ok(Charset::Digits),
+            "ascii" => Ok(Charset::Ascii),
+            "custom" => bail!("charset custom requires chars"),
+            _ => Err(format_err!("unsupported charset {}", charset)),
+        }
+    }
+}
+
+/// How `/bytes` spells the bytes it returns.
+#[derive(Clone, Copy)]
+enum ByteEncoding {
+    Hex,
+    Base64,
+    Base64Url,
+}
+
+impl FromStr for ByteEncoding {
+    type Err = Error;
+
+    fn from_str(encoding: &str) -> Result<Self, Error> {
+        match encoding {
+            "hex" => Ok(ByteEncoding::Hex),
+            "base64" => Ok(ByteEncoding::Base64),
+            "base64url" => Ok(ByteEncoding::Base64Url),
+            _ => Err(format_err!("unsupported encoding {}", encoding)),
+        }
+    }
+}
+
+impl ByteEncoding {
+    fn encode(self, bytes: &[u8]) -> String {
+        match self {
+            ByteEncoding::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            ByteEncoding::Base64 => BASE64.encode(bytes),
+            ByteEncoding::Base64Url => BASE64_URL.encode(bytes),
+        }
+    }
+}
+
+/// Identifiers `/uuid` and `/ulid` return. The time-ordered kinds take their
+/// timestamp from the clock and only the remaining bits from the generator.
+#[derive(Clone, Copy)]
+enum IdKind {
+    UuidV4,
+    UuidV7,
+    Ulid,
+}
+
+impl IdKind {
+    fn generate<R: Rng + ?Sized>(self, rng: &mut R) -> String {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        match self {
+            IdKind::UuidV4 => uuid::Builder::from_random_bytes(rng.gen())
+                .into_uuid()
+                .to_string(),
+            IdKind::UuidV7 => uuid::Builder::from_unix_timestamp_millis(millis, &rng.gen())
+                .into_uuid()
+                .to_string(),
+            IdKind::Ulid => ulid::Ulid::from_parts(millis, rng.gen()).to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum QuasiSequence {
+    Sobol,
+    Halton,
+}
+
+/// The body of `POST /quasirandom`: `count` points of `sequence` in
+/// `dimensions` dimensions, starting at index `skip`, or where the last
+/// request for the same `stream` stopped.
+#[derive(Deserialize, Serialize)]
+struct QuasiRequest {
+    sequence: QuasiSequence,
+    dimensions: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    skip: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream: Option<String>,
+}
+
+/// The points and the index a stateless client passes as `skip` to continue.
+#[derive(Serialize)]
+struct QuasiResponse {
+    points: Vec<Vec<f64>>,
+    next: u64,
+}
+
+fn quasirandom(request: &QuasiRequest, state: &State) -> Result<QuasiResponse, Error> {
+    let config = &state.config();
+    let (max_dimensions, period) = match request.sequence {
+        QuasiSequence::Sobol => (quasi::MAX_SOBOL_DIMENSIONS, quasi::SOBOL_PERIOD),
+        QuasiSequence::Halton => (MAX_DIMENSIONS as usize, u64::MAX),
+    };
+    if request.dimensions == 0 || request.dimensions > max_dimensions {
+        bail!(
+            "dimensions must be between 1 and {}, got {}",
+            max_dimensions,
+            request.dimensions
+        );
+    }
+    let count = request.count.unwrap_or(1);
+    if count > config.max_count || count * request.dimensions * 8 > config.max_batch_bytes {
+        bail!(
+            "{} points of {} dimensions exceed the batch limits",
+            count,
+            request.dimensions
+        );
+    }
+    let start = match (request.skip, &request.stream) {
+        (Some(_), Some(_)) => bail!("skip cannot be combined with stream"),
+        (skip, None) => skip.unwrap_or(0),
+        (None, Some(name)) => state.advance_quasi_stream(name, request, count as u64, period)?,
+    };
+    let end = start
+        .checked_add(count as u64)
+        .filter(|&end| end <= period)
+        .ok_or_else(|| format_err!("the sequence ends after {} points", period))?;
+    let points = match request.sequence {
+        QuasiSequence::Sobol => {
+            let directions = quasi::sobol_directions(request.dimensions);
+            (start..end)
+                .map(|index| quasi::sobol(&directions, index))
+                .collect()
+        }
+        QuasiSequence::Halton => {
+            let bases = quasi::primes(request.dimensions);
+            (start..end)
+                .map(|index| quasi::halton(&bases, index))
+                .collect()
+        }
+    };
+    Ok(QuasiResponse { points, next: end })
+}
+
+/// The body of `POST /sample` and `POST /shuffle`: the `items` to draw from,
+/// or `n` for the integers `0..n`, and for `/sample` how many to draw.
+#[derive(Deserialize, Serialize)]
+struct DrawRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    items: Option<Vec<Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    n: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    k: Option<usize>,
+}
+
+impl DrawRequest {
+    fn len(&self, config: &Config) -> Result<usize, Error> {
+        let len = match (&self.items, self.n) {
+            (Some(items), None) => items.len(),
+            (None, Some(n)) => n,
+            (Some(_), Some(_)) => bail!("give either items or n, not both"),
+            (None, None) => bail!("missing items or n"),
+        };
+        if len > config.max_count {
+            bail!("{} items exceeds the maximum of {}", len, config.max_count);
+        }
+        Ok(len)
+    }
+
+    fn items(&self, indices: impl Iterator<Item = usize>) -> Vec<Value> {
+        indices
+            .map(|index| match self.items {
+                Some(ref items) => items[index].clone(),
+                None => Value::from(index),
+            })
+            .collect()
+    }
+}
+
+impl Charset {
+    fn custom(chars: &str) -> Result<Self, Error> {
+        let mut distinct = Vec::new();
+        for c in chars.chars() {
+            if !distinct.contains(&c) {
+                distinct.push(c);
+            }
+        }
+        if distinct.is_empty() {
+            bail!("charset custom requires at least one character in chars");
+        }
+        Ok(Charset::Custom(distinct))
+    }
+
+    fn generate<R: Rng + ?Sized>(&self, rng: &mut R, len: usize) -> String {
+        let chars: &[u8] = match *self {
+            Charset::Alphanumeric => return (0..len).map(|_| rng.sample(Alphanumeric)).collect(),
+            Charset::Ascii => {
+                return (0..len)
+                    .map(|_| rng.gen_range(b'!', b'~' + 1) as char)
+                    .collect()
+            }
+            Charset::Custom(ref chars) => {
+                return (0..len)
+                    .map(|_| chars[rng.gen_range(0, chars.len())])
+                    .collect()
+            }
+            Charset::Hex => b"0123456789abcdef",
+            Charset::Alpha => b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz",
+            Charset::Digits => b"0123456789",
+        };
+        (0..len)
+            .map(|_| chars[rng.gen_range(0, chars.len())] as char)
+            .collect()
+    }
+}
+
+const PASSWORD_SYMBOLS: &[u8] = b"!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// The body of `POST /password`. Lowercase letters are always allowed and the
+/// other classes unless switched off; `require_*` asks for at least one
+/// character of that class.
+#[derive(Deserialize, Serialize)]
+#[serde(default)]
+struct PasswordPolicy {
+    length: usize,
+    upper: bool,
+    digits: bool,
+    symbols: bool,
+    require_upper: bool,
+    require_digits: bool,
+    require_symbols: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        PasswordPolicy {
+            length: 16,
+            upper: true,
+            digits: true,
+            symbols: true,
+            require_upper: false,
+            require_digits: false,
+            require_symbols: false,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    /// The allowed characters, or why no password can satisfy the policy.
+    fn alphabet(&self, config: &Config) -> Result<Vec<u8>, Error> {
+        if self.length == 0 || self.length > config.max_string_len {
+            bail!(
+                "length must be between 1 and {}, got {}",
+                config.max_string_len,
+                self.length
+            );
+        }
+        let classes: [(&str, bool, bool, &[u8]); 3] = [
+            (
+                "upper",
+                self.upper,
+                self.require_upper,
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZ",
+            ),
+            ("digits", self.digits, self.require_digits, b"0123456789"),
+            (
+                "symbols",
+                self.symbols,
+                self.require_symbols,
+                PASSWORD_SYMBOLS,
+            ),
+        ];
+        let mut alphabet = b"abcdefghijklmnopqrstuvwxyz".to_vec();
+        let mut required = 0;
+        for &(name, allowed, require, chars) in &classes {
+            match (allowed, require) {
+                (false, true) => bail!("require_{} cannot be met with {} disabled", name, name),
+                (true, require) => {
+                    alphabet.extend_from_slice(chars);
+                    required += require as usize;
+                }
+                (false, false) => {}
+            }
+        }
+        if required > self.length {
+            bail!(
+                "a password of length {} cannot contain all {} required classes",
+                self.length,
+                required
+            );
+        }
+        Ok(alphabet)
+    }
+
+    /// Draws uniformly from the passwords over `alphabet` that meet the
+    /// requirements, by redrawing the ones that do not.
+    fn generate<R: Rng + ?Sized>(&self, alphabet: &[u8], rng: &mut R) -> String {
+        loop {
+            let password = (0..self.length)
+                .map(|_| alphabet[rng.gen_range(0, alphabet.len())])
+                .collect::<Vec<_>>();
+            let has = |predicate: fn(&u8) -> bool| password.iter().any(predicate);
+            if (!self.require_upper || has(u8::is_ascii_uppercase))
+                && (!self.require_digits || has(u8::is_ascii_digit))
+                && (!self.require_symbols || has(|c| PASSWORD_SYMBOLS.contains(c)))
+            {
+                return password.into_iter().map(char::from).collect();
+            }
+        }
+    }
+}
+
+/// Upper bounds, in seconds, of the request latency histogram's buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.000_5, 0.001, 0.002_5, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+
+/// The counters `/metrics` exposes, registered in a registry of their own.
+struct Metrics {
+    registry: Registry,
+    responses: IntCounterVec,
+    rejections: IntCounterVec,
+    requests: IntCounterVec,
+    durations: HistogramVec,
+    random_requests: IntCounterVec,
+    random_values: IntCounterVec,
+    /// What each of the `workers` accept loops has handled.
+    worker_connections: IntCounterVec,
+    worker_requests: IntCounterVec,
+    in_flight: IntGauge,
+}
+
+impl Metrics {
+    fn new(workers: usize) -> Result<Self, Error> {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str, labels: &[&str]| -> Result<_, Error> {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let responses = counter(
+            "http_responses_total",
+            "Responses returned, by status code.",
+            &["code"],
+        )?;
+        let rejections = counter(
+            "rejections_total",
+            "Rejected requests, by reason.",
+            &["reason"],
+        )?;
+        let requests = counter(
+            "http_requests_total",
+            "Requests handled, by endpoint and status code.",
+            &["endpoint", "code"],
+        )?;
+        let random_requests = counter(
+            "random_requests_total",
+            "Successful /random requests, by distribution.",
+            &["distribution"],
+        )?;
+        let random_values = counter(
+            "random_values_total",
+            "Values returned by /random, by distribution.",
+            &["distribution"],
+        )?;
+        let worker_connections = counter(
+            "worker_connections_total",
+            "Connections accepted, by worker.",
+            &["worker"],
+        )?;
+        let worker_requests = counter(
+            "worker_requests_total",
+            "Requests received, by worker.",
+            &["worker"],
+        )?;
+        let durations = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to produce a response, by endpoint.",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["endpoint"],
+        )?;
+        registry.register(Box::new(durations.clone()))?;
+        let in_flight = IntGauge::new("requests_in_flight", "Requests currently being handled.")?;
+        registry.register(Box::new(in_flight.clone()))?;
+        // Every reason and worker is listed from the start, at zero.
+        for rejection in Rejection::ALL {
+            rejections.with_label_values(&[rejection.reason()]);
+        }
+        for worker in 0..workers {
+            let worker = worker.to_string();
+            worker_connections.with_label_values(&[&worker]);
+            worker_requests.with_label_values(&[&worker]);
+        }
+        Ok(Metrics {
+            registry,
+            responses,
+            rejections,
+            requests,
+            durations,
+            random_requests,
+            random_values,
+            worker_connections,
+            worker_requests,
+            in_flight,
+        })
+    }
+}
+
+/// The config and what is built from it, replaced together by
+/// `/admin/reload`.
+struct Settings {
+    config: Arc<Config>,
+    rate_limiter: Option<RateLimiter>,
+    distribution_limiters: HashMap<String, RateLimiter>,
+    signing_key: Option<Vec<u8>>,
+    response_headers: HeaderMap,
+}
+
+impl Settings {
+    fn new(config: Config) -> Result<Self, Error> {
+        let response_headers = response_headers(&config.response_headers)
+            .map_err(|err| format_err!("invalid response_headers: {}", err))?;
+        let signing_key = match config.signing_key_path {
+            Some(ref path) => {
+                let key = fs::read_to_string(path)
+                    .map_err(|err| format_err!("cannot read signing key: {}", err))?;
+                Some(key.trim().as_bytes().to_vec())
+            }
+            None => None,
+        };
+        Ok(Settings {
+            rate_limiter: config.rate_limit.map(RateLimiter::new).transpose()?,
+            distribution_limiters: config
+                .distribution_rate_limits
+                .iter()
+                .map(|(name, &limit)| Ok((name.clone(), RateLimiter::new(limit)?)))
+                .collect::<Result<_, Error>>()?,
+            signing_key,
+            response_headers,
+            config: Arc::new(config),
+        })
+    }
+}
+
+/// Loads the config again for `/admin/reload`.
+type ConfigLoader = Box<dyn Fn() -> Result<Config, Error> + Send + Sync>;
+
+pub struct State {
+    settings: RwLock<Arc<Settings>>,
+    loader: Option<ConfigLoader>,
+    metrics: Metrics,
+    in_flight: AtomicUsize,
+    seed: u64,
+    shared_rng: Mutex<StdRng>,
+    threads: AtomicUsize,
+    ready: AtomicBool,
+    streams: RwLock<HashMap<String, Mutex<NamedStream>>>,
+    /// Each named `/quasirandom` stream's sequence, dimensions and next index.
+    quasi_streams: Mutex<HashMap<String, (QuasiSequence, usize, u64)>>,
+    load_monitor: Option<LoadMonitor>,
+    audit_log: Option<AuditLog>,
+    /// Accepts TLS on the TCP listeners when `tls` is configured.
+    tls: Option<TlsAcceptor>,
+    /// Bumped by every `/admin/loglevel` change, so a temporary level only
+    /// reverts if nothing changed it since.
+    log_level_changes: AtomicUsize,
+}
+
+/// Whose requests share a token bucket.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    ApiKey(String),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<Client, Bucket>>,
+}
+
+const MAX_RATE_LIMIT_BUCKETS: usize = 100_000;
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Result<Self, Error> {
+        if !(limit.requests_per_second > 0.0 && limit.burst >= 1.0) {
+            bail!("rate limits need a positive requests_per_second and a burst of at least 1");
+        }
+        Ok(RateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Takes a token for `client`, or returns how long until one is available.
+    fn acquire(&self, client: Client) -> Result<(), Duration> {
+        let now = Instant::now();
+        let RateLimit {
+            requests_per_second: rate,
+            burst,
+            ..
+        } = self.limit;
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            (bucket.tokens + elapsed * rate).min(burst)
+        };
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_RATE_LIMIT_BUCKETS {
+            buckets.retain(|_, bucket| refill(bucket) < burst);
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RateLimited {
+    scope: String,
+    retry_after: Duration,
+}
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rate limit exceeded for {}", self.scope)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+#[derive(Debug)]
+struct Overloaded {
+    retry_after: Duration,
+}
+
+impl Display for Overloaded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "server is overloaded")
+    }
+}
+
+impl std::error::Error for Overloaded {}
+
+/// Samples system load from a background thread so requests only read a flag.
+struct LoadMonitor {
+    overloaded: Arc<AtomicBool>,
+    interval: Duration,
+}
+
+impl LoadMonitor {
+    fn start(limits: LoadShedding) -> Self {
+        let overloaded = Arc::new(AtomicBool::new(false));
+        let interval = Duration::from_millis(limits.check_interval_ms)
+            .max(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        let flag = overloaded.clone();
+        thread::spawn(move || {
+            let mut system = System::new();
+            loop {
+                system.refresh_cpu_usage();
+                system.refresh_memory();
+                let cpu = system.global_cpu_info().cpu_usage();
+                let memory =
+                    system.used_memory() as f32 / system.total_memory().max(1) as f32 * 100.0;
+                let over = limits.max_cpu_percent.is_some_and(|max| cpu > max)
+                    || limits.max_memory_percent.is_some_and(|max| memory > max);
+                match (flag.swap(over, Ordering::SeqCst), over) {
+                    (false, true) => warn!("Shedding load: cpu={:.1}% memory={:.1}%", cpu, memory),
+                    (true, false) => info!("Load recovered: cpu={:.1}% memory={:.1}%", cpu, memory),
+                    _ => {}
+                }
+                thread::sleep(interval);
+            }
+        });
+        LoadMonitor {
+            overloaded,
+            interval,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AccessRecord<'a> {
+    timestamp: String,
+    request_id: &'a str,
+    remote: IpAddr,
+    method: &'a str,
+    path: &'a str,
+    status: u16,
+    latency_ms: f64,
+}
+
+/// The key in `X-Api-Key` or an `Authorization: Bearer` header.
+fn presented_api_key(headers: &HeaderMap) -> Option<&[u8]> {
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, key)| key.trim().as_bytes());
+    headers
+        .get("x-api-key")
+        .map(HeaderValue::as_bytes)
+        .or(bearer)
+}
+
+/// The client's `X-Request-Id` when it is short printable ASCII, otherwise a
+/// new random one.
+fn request_id(headers: &HeaderMap) -> HeaderValue {
+    match headers.get("x-request-id") {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= 128
+                && id.as_bytes().iter().all(u8::is_ascii_graphic) =>
+        {
+            id.clone()
+        }
+        _ => HeaderValue::from_str(&format!("{:016x}", rand::random::<u64>())).unwrap(),
+    }
+}
+
+#[derive(Serialize)]
+struct AuditRecord {
+    timestamp_ms: u64,
+    request_id: String,
+    request: Value,
+    result: Value,
+}
+
+/// Appends audit records from a background thread, so requests only pay for
+/// queueing a record rather than for the write.
+struct AuditLog {
+    sender: Mutex<Option<mpsc::Sender<AuditRecord>>>,
+    writer: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl AuditLog {
+    fn open(path: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let open = |path: &Path| OpenOptions::new().create(true).append(true).open(path);
+        let mut file = open(&path)?;
+        let mut written = file.metadata()?.len();
+        let (sender, receiver) = mpsc::channel::<AuditRecord>();
+        let writer = thread::spawn(move || {
+            for record in receiver {
+                let mut line = serde_json::to_vec(&record).unwrap();
+                line.push(b'\n');
+                if written > 0 && written + line.len() as u64 > max_bytes {
+                    let mut rotated = path.clone().into_os_string();
+                    rotated.push(".1");
+                    let res = fs::rename(&path, &rotated).and_then(|()| open(&path));
+                    match res {
+                        Ok(rotated) => {
+                            file = rotated;
+                            written = 0;
+                        }
+                        Err(err) => warn!("Cannot rotate audit log {}: {}", path.display(), err),
+                    }
+                }
+                match file.write_all(&line) {
+                    Ok(()) => written += line.len() as u64,
+                    Err(err) => warn!("Cannot write audit log {}: {}", path.display(), err),
+                }
+            }
+        });
+        Ok(AuditLog {
+            sender: Mutex::new(Some(sender)),
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+
+    fn record(&self, record: AuditRecord) {
+        if let Some(ref sender) = *self.sender.lock().unwrap() {
+            // The writer only stops once the sender is dropped in `close`.
+            let _ = sender.send(record);
+        }
+    }
+
+    /// Stops accepting records and waits for the queued ones to be written.
+    fn close(&self) {
+        self.sender.lock().unwrap().take();
+        if let Some(writer) = self.writer.lock().unwrap().take() {
+            if writer.join().is_err() {
+                warn!("Audit log writer panicked");
+            }
+        }
+    }
+}
+
+/// A generator selected with `?stream=`, seeded from the server seed and its
+/// name so it replays identically after a reset or restart with the same seed.
+struct NamedStream {
+    base_seed: u64,
+    rng: StdRng,
+    draws: u64,
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash: u64, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+impl NamedStream {
+    fn new(seed: u64, name: &str) -> Self {
+        let base_seed = seed ^ fnv1a(name.as_bytes());
+        NamedStream {
+            base_seed,
+            rng: StdRng::seed_from_u64(base_seed),
+            draws: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.base_seed);
+        self.draws = 0;
+    }
+}
+
+thread_local! {
+    static THREAD_RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+    static CHACHA_RNG: RefCell<Option<ChaChaRng>> = const { RefCell::new(None) };
+    static PCG_RNG: RefCell<Option<Pcg64Mcg>> = const { RefCell::new(None) };
+}
+
+/// The configured API key equal to `key`. Every key is compared in full so
+/// the time taken does not reveal how much of a guess matched.
+fn known_api_key<'a>(config: &'a Config, key: &[u8]) -> Option<&'a str> {
+    config.api_keys.iter().fold(None, |known, candidate| {
+        let differences = candidate
+            .bytes()
+            .zip(key)
+            .fold(0, |differences, (a, b)| differences | (a ^ b));
+        if candidate.len() == key.len() && differences == 0 {
+            Some(candidate.as_str())
+        } else {
+            known
+        }
+    })
+}
+
+impl State {
+    pub fn new(config: Config) -> Result<Self, Error> {
+        let seed = config.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        match config.rng_mode {
+            RngMode::ThreadLocal => {}
+            mode => info!("Using {:?} RNG mode with seed {}", mode, seed),
+        }
+        let audit_log = match config.audit_log_path {
+            Some(ref path) => Some(
+                AuditLog::open(path.clone(), config.audit_log_max_bytes)
+                    .map_err(|err| format_err!("cannot open audit log: {}", err))?,
+            ),
+            None => None,
+        };
+        let tls = match config.tls {
+            Some(ref tls) => Some(TlsAcceptor::from(Arc::new(tls_config(tls)?))),
+            None => None,
+        };
+        let load_monitor = config.load_shedding.map(LoadMonitor::start);
+        let metrics = Metrics::new(config.workers.max(1))?;
+        let settings = Settings::new(config)?;
+        Ok(State {
+            settings: RwLock::new(Arc::new(settings)),
+            loader: None,
+            metrics,
+            in_flight: AtomicUsize::new(0),
+            seed,
+            shared_rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            threads: AtomicUsize::new(0),
+            ready: AtomicBool::new(false),
+            streams: RwLock::new(HashMap::new()),
+            quasi_streams: Mutex::new(HashMap::new()),
+            load_monitor,
+            audit_log,
+            tls,
+            log_level_changes: AtomicUsize::new(0),
+        })
+    }
+
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    fn config(&self) -> Arc<Config> {
+        self.settings().config.clone()
+    }
+
+    /// Loads the config again and puts it in place for new requests. The
+    /// listeners, seed, audit log and load shedding keep the settings they
+    /// started with, and rate limit buckets start out full again.
+    fn reload(&self) -> Result<(), Error> {
+        let load = self
+            .loader
+            .as_ref()
+            .ok_or_else(|| format_err!("there is no config to reload"))?;
+        let settings = Settings::new(load()?)?;
+        *self.settings.write().unwrap() = Arc::new(settings);
+        info!("Reloaded config");
+        Ok(())
+    }
+
+    fn with_rng<T>(
+        &self,
+        source: EntropySource,
+        f: impl FnOnce(&mut dyn RngCore) -> T,
+    ) -> Result<T, Error> {
+        let value = match (source, self.config().rng_mode) {
+            (EntropySource::OsRng, _) => f(&mut OsRng::new()?),
+            (EntropySource::Chacha, _) => CHACHA_RNG.with(|rng| {
+                let mut rng = rng.borrow_mut();
+                f(rng.get_or_insert_with(|| ChaChaRng::seed_from_u64(self.thread_seed())))
+            }),
+            (EntropySource::Pcg, _) => PCG_RNG.with(|rng| {
+                let mut rng = rng.borrow_mut();
+                f(rng.get_or_insert_with(|| Pcg64Mcg::seed_from_u64(self.thread_seed())))
+            }),
+            (EntropySource::ThreadRng, RngMode::ThreadLocal) => f(&mut rand::thread_rng()),
+            (EntropySource::ThreadRng, RngMode::SharedSeeded) => {
+                f(&mut *self.shared_rng.lock().unwrap())
+            }
+            (EntropySource::ThreadRng, RngMode::PerThreadSeeded) => THREAD_RNG.with(|rng| {
+                let mut rng = rng.borrow_mut();
+                f(rng.get_or_insert_with(|| StdRng::seed_from_u64(self.thread_seed())))
+            }),
+        };
+        Ok(value)
+    }
+
+    fn thread_seed(&self) -> u64 {
+        let thread = self.threads.fetch_add(1, Ordering::SeqCst) as u64;
+        self.seed.wrapping_add(thread)
+    }
+
+    fn with_stream<T>(
+        &self,
+        name: &str,
+        draws: usize,
+        f: impl FnOnce(&mut dyn RngCore) -> T,
+    ) -> Result<T, Error> {
+        let run = |stream: &mut NamedStream| {
+            stream.draws += draws as u64;
+            f(&mut stream.rng)
+        };
+        if let Some(stream) = self.streams.read().unwrap().get(name) {
+            return Ok(run(&mut stream.lock().unwrap()));
+        }
+        let mut streams = self.streams.write().unwrap();
+        if !streams.contains_key(name) && streams.len() >= self.config().max_streams {
+            bail!(
+                "cannot create stream {:?}: limit of {} streams reached",
+                name,
+                self.config().max_streams
+            );
+        }
+        let stream = streams
+            .entry(name.to_owned())
+            .or_insert_with(|| Mutex::new(NamedStream::new(self.seed, name)));
+        Ok(run(stream.get_mut().unwrap()))
+    }
+
+    /// Reserves `count` points of the named quasi-random stream, returning the
+    /// index of the first.
+    fn advance_quasi_stream(
+        &self,
+        name: &str,
+        request: &QuasiRequest,
+        count: u64,
+        period: u64,
+    ) -> Result<u64, Error> {
+        let mut streams = self.quasi_streams.lock().unwrap();
+        if !streams.contains_key(name) && streams.len() >= self.config().max_streams {
+            bail!(
+                "cannot create stream {:?}: limit of {} streams reached",
+                name,
+                self.config().max_streams
+            );
+        }
+        let stream =
+            streams
+                .entry(name.to_owned())
+                .or_insert((request.sequence, request.dimensions, 0));
+        if (stream.0, stream.1) != (request.sequence, request.dimensions) {
+            bail!(
+                "stream {:?} was started with a different sequence or dimensions",
+                name
+            );
+        }
+        let start = stream.2;
+        if start.saturating_add(count) > period {
+            bail!("the sequence ends after {} points", period);
+        }
+        stream.2 += count;
+        Ok(start)
+    }
+
+    /// The hex HMAC-SHA256 of `body` under the signing key, if there is one.
+    fn sign(&self, body: &[u8]) -> Option<String> {
+        let settings = self.settings();
+        let key = settings.signing_key.as_ref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(body);
+        Some(ByteEncoding::Hex.encode(&mac.finalize().into_bytes()))
+    }
+
+    /// Checks for one of the configured API keys, when there are any.
+    fn authenticate(&self, headers: &HeaderMap) -> Result<(), (Rejection, &'static str)> {
+        let config = self.config();
+        if config.api_keys.is_empty() {
+            return Ok(());
+        }
+        let key = presented_api_key(headers).ok_or((Rejection::Unauthorized, "missing API key"))?;
+        match known_api_key(&config, key) {
+            Some(_) => Ok(()),
+            None => Err((Rejection::Forbidden, "invalid API key")),
+        }
+    }
+
+    fn check_rate_limit(
+        &self,
+        remote: &SocketAddr,
+        headers: &HeaderMap,
+    ) -> Result<(), RateLimited> {
+        let settings = self.settings();
+        let limiter = match settings.rate_limiter {
+            Some(ref limiter) => limiter,
+            None => return Ok(()),
+        };
+        let key = match limiter.limit.key_by {
+            RateLimitKey::ApiKey => {
+                presented_api_key(headers).and_then(|key| known_api_key(&settings.config, key))
+            }
+            RateLimitKey::Ip => None,
+        };
+        let (client, scope) = match key {
+            Some(key) => (Client::ApiKey(key.to_owned()), "this API key".to_owned()),
+            None => (Client::Ip(remote.ip()), remote.ip().to_string()),
+        };
+        limiter
+            .acquire(client)
+            .map_err(|retry_after| RateLimited { scope, retry_after })
+    }
+
+    fn check_load(&self) -> Result<(), Overloaded> {
+        match self.load_monitor {
+            Some(ref monitor) if monitor.overloaded.load(Ordering::SeqCst) => Err(Overloaded {
+                retry_after: monitor.interval,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_distribution_limit(
+        &self,
+        remote: &SocketAddr,
+        request: &RngRequest,
+    ) -> Result<(), RateLimited> {
+        match self.settings().distribution_limiters.get(request.name()) {
+            Some(limiter) => limiter
+                .acquire(Client::Ip(remote.ip()))
+                .map_err(|retry_after| RateLimited {
+                    scope: request.name().to_owned(),
+                    retry_after,
+                }),
+            None => Ok(()),
+        }
+    }
+
+    fn audit<R: Serialize, T: Serialize>(&self, request_id: &str, request: &R, result: &T) {
+        if let Some(ref audit_log) = self.audit_log {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            audit_log.record(AuditRecord {
+                timestamp_ms: timestamp.as_millis() as u64,
+                request_id: request_id.to_owned(),
+                request: serde_json::to_value(request).unwrap_or(Value::Null),
+                result: serde_json::to_value(result).unwrap_or(Value::Null),
+            });
+        }
+    }
+
+    /// Draws once from every distribution, so the calling thread's generator
+    /// and any state built on first use exist before real traffic arrives.
+    fn warm_up(&self) -> Result<Vec<&'static str>, Error> {
+        let mut warmed = Vec::new();
+        for request in RngRequest::defaults() {
+            self.with_rng(self.config().entropy_source, |rng| sample(&request, rng))??;
+            if !warmed.contains(&request.name()) {
+                warmed.push(request.name());
+            }
+        }
+        Ok(warmed)
+    }
+
+    fn count_response(&self, status: StatusCode) {
+        let code = status.as_u16().to_string();
+        self.metrics.responses.with_label_values(&[&code]).inc();
+    }
+
+    /// Records a finished request for `/metrics`.
+    fn observe(&self, path: &str, resp: &Response<Body>, elapsed: Duration) {
+        let endpoint = router::endpoint(path);
+        let code = resp.status().as_u16().to_string();
+        let metrics = &self.metrics;
+        metrics.requests.with_label_values(&[endpoint, &code]).inc();
+        metrics
+            .durations
+            .with_label_values(&[endpoint])
+            .observe(elapsed.as_secs_f64());
+        if let Some(generated) = resp.extensions().get::<Generated>() {
+            let distribution = [generated.distribution];
+            metrics
+                .random_requests
+                .with_label_values(&distribution)
+                .inc();
+            metrics
+                .random_values
+                .with_label_values(&distribution)
+                .inc_by(generated.count as u64);
+        }
+    }
+
+    /// Response, rejection, latency and per-distribution counters in the
+    /// Prometheus text format.
+    fn metrics(&self) -> Result<Vec<u8>, Error> {
+        let in_flight = self.in_flight.load(Ordering::SeqCst);
+        self.metrics.in_flight.set(in_flight as i64);
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.metrics.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn log_access(
+        &self,
+        request_id: &str,
+        remote: &SocketAddr,
+        method: &Method,
+        path: &str,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        let failed = status.is_client_error() || status.is_server_error();
+        if !failed && rand::thread_rng().gen::<f64>() >= self.config().log_sample_rate {
+            return;
+        }
+        match self.config().log_format {
+            LogFormat::Text => info!(
+                "{} {} {} {} {} {:?}",
+                request_id,
+                remote.ip(),
+                method,
+                path,
+                status.as_u16(),
+                elapsed
+            ),
+            // Written to stderr directly so each line is a bare JSON document,
+            // but only when the logger would show info lines, so the log level
+            // and `/admin/loglevel` govern these as they do the text ones.
+            LogFormat::Json if log::log_enabled!(log::Level::Info) => {
+                let record = AccessRecord {
+                    timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    request_id,
+                    remote: remote.ip(),
+                    method: method.as_str(),
+                    path,
+                    status: status.as_u16(),
+                    latency_ms: elapsed.as_secs_f64() * 1000.0,
+                };
+                match serde_json::to_string(&record) {
+                    // A failed write to stderr has nowhere to be reported.
+                    Ok(line) => drop(writeln!(io::stderr().lock(), "{}", line)),
+                    Err(err) => warn!("Cannot serialize access record: {}", err),
+                }
+            }
+            LogFormat::Json => {}
+        }
+    }
+
+    fn warn_if_slow(&self, request_id: &str, path: &str, resp: &Response<Body>, elapsed: Duration) {
+        let threshold = match self.config().slow_request_threshold_ms {
+            Some(threshold) => Duration::from_millis(threshold),
+            None => return,
+        };
+        if elapsed <= threshold {
+            return;
+        }
+        match resp.extensions().get::<Generated>() {
+            Some(generated) => warn!(
+                "slow request: request_id={} path={} distribution={} count={} elapsed={:?}",
+                request_id, path, generated.distribution, generated.count, elapsed
+            ),
+            None => warn!(
+                "slow request: request_id={} path={} elapsed={:?}",
+                request_id, path, elapsed
+            ),
+        }
+    }
+
+    fn reject(
+        &self,
+        remote: &SocketAddr,
+        rejection: Rejection,
+        message: String,
+        format: Format,
+    ) -> Response<Body> {
+        let error = ErrorResponse {
+            error: ApiError {
+                code: rejection.reason(),
+                message,
+                details: Vec::new(),
+            },
+        };
+        self.reject_with(remote, rejection, &error, format)
+    }
+
+    fn reject_with(
+        &self,
+        remote: &SocketAddr,
+        rejection: Rejection,
+        error: &ErrorResponse,
+        format: Format,
+    ) -> Response<Body> {
+        warn!(
+            "rejected request: reason={} client={} status={} message={:?}",
+            rejection.reason(),
+            remote.ip(),
+            rejection.status().as_u16(),
+            error.error.message
+        );
+        self.metrics
+            .rejections
+            .with_label_values(&[rejection.reason()])
+            .inc();
+        let body = serialize(format, error).unwrap();
+        Response::builder()
+            .status(rejection.status())
+            .header(CONTENT_TYPE, format.content_type())
+            .body(body.into())
+            .unwrap()
+    }
+
+    fn reject_error(&self, remote: &SocketAddr, err: &Error, format: Format) -> Response<Body> {
+        let rejection = Rejection::from_error(err);
+        let mut resp = self.reject_with(remote, rejection, &ErrorResponse::new(err), format);
+        let retry_after = err
+            .downcast_ref::<RateLimited>()
+            .map(|limited| limited.retry_after)
+            .or_else(|| {
+                err.downcast_ref::<Overloaded>()
+                    .map(|over| over.retry_after)
+            });
+        if let Some(retry_after) = retry_after {
+            let seconds = retry_after.as_secs_f64().ceil() as u64;
+            resp.headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(seconds.max(1)));
+        }
+        resp
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Rejection {
+    NotFound,
+    Unprocessable,
+    NotImplemented,
+    AssertionFailed,
+    SamplingFailed,
+    PayloadTooLarge,
+    RateLimited,
+    Overloaded,
+    BadRequest,
+    UnsupportedMediaType,
+    Unauthorized,
+    Forbidden,
+    MethodNotAllowed,
+    RequestTimeout,
+}
+
+impl Rejection {
+    const ALL: &'static [Rejection] = &[
+        Rejection::NotFound,
+        Rejection::Unprocessable,
+        Rejection::NotImplemented,
+        Rejection::AssertionFailed,
+        Rejection::SamplingFailed,
+        Rejection::PayloadTooLarge,
+        Rejection::RateLimited,
+        Rejection::Overloaded,
+        Rejection::BadRequest,
+        Rejection::UnsupportedMediaType,
+        Rejection::Unauthorized,
+        Rejection::Forbidden,
+        Rejection::MethodNotAllowed,
+        Rejection::RequestTimeout,
+    ];
+
+    fn from_error(err: &Error) -> Self {
+        if err.downcast_ref::<AssertionFailed>().is_some() {
+            Rejection::AssertionFailed
+        } else if err.downcast_ref::<SamplingFailed>().is_some() {
+            Rejection::SamplingFailed
+        } else if err.downcast_ref::<NoDensity>().is_some() {
+            Rejection::NotImplemented
+        } else if err.downcast_ref::<RateLimited>().is_some() {
+            Rejection::RateLimited
+        } else if err.downcast_ref::<Overloaded>().is_some() {
+            Rejection::Overloaded
+        } else if err.downcast_ref::<InvalidType>().is_some() {
+            Rejection::BadRequest
+        } else {
+            Rejection::Unprocessable
+        }
+    }
+
+    fn reason(self) -> &'static str {
+        match self {
+            Rejection::NotFound => "not_found",
+            Rejection::Unprocessable => "unprocessable",
+            Rejection::NotImplemented => "not_implemented",
+            Rejection::AssertionFailed => "assertion_failed",
+            Rejection::SamplingFailed => "sampling_failed",
+            Rejection::PayloadTooLarge => "payload_too_large",
+            Rejection::RateLimited => "rate_limited",
+            Rejection::Overloaded => "overloaded",
+            Rejection::BadRequest => "bad_request",
+            Rejection::UnsupportedMediaType => "unsupported_media_type",
+            Rejection::Unauthorized => "unauthorized",
+            Rejection::Forbidden => "forbidden",
+            Rejection::MethodNotAllowed => "method_not_allowed",
+            Rejection::RequestTimeout => "request_timeout",
+        }
+    }
+
+    fn status(self) -> StatusCode {
+        match self {
+            Rejection::NotFound => StatusCode::NOT_FOUND,
+            Rejection::Unprocessable => StatusCode::UNPROCESSABLE_ENTITY,
+            Rejection::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Rejection::AssertionFailed | Rejection::SamplingFailed => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Rejection::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Rejection::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            Rejection::Overloaded => StatusCode::SERVICE_UNAVAILABLE,
+            Rejection::BadRequest => StatusCode::BAD_REQUEST,
+            Rejection::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Rejection::Unauthorized => StatusCode::UNAUTHORIZED,
+            Rejection::Forbidden => StatusCode::FORBIDDEN,
+            Rejection::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Rejection::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: ApiError,
+}
+
+/// What went wrong: `code` is the rejection reason `/stats` counts it under,
+/// and `details` lists each invalid parameter when there are several.
+#[derive(Serialize)]
+struct ApiError {
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<FieldError>,
+}
+
+impl ErrorResponse {
+    fn new(err: &Error) -> Self {
+        ErrorResponse {
+            error: ApiError {
+                code: Rejection::from_error(err).reason(),
+                message: err.to_string(),
+                details: err
+                    .downcast_ref::<InvalidParameters>()
+                    .map(|invalid| invalid.0.clone())
+                    .unwrap_or_default(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatusResponse {
+    status: &'static str,
+}
+
+#[derive(Serialize)]
+struct ReadyResponse {
+    status: &'static str,
+    checks: ReadyChecks,
+}
+
+#[derive(Serialize)]
+struct ReadyChecks {
+    listener: &'static str,
+    load: &'static str,
+}
+
+#[derive(Serialize)]
+struct StatsResponse<'a> {
+    rejections: &'a BTreeMap<&'static str, u64>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Identity,
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`.
+    Deflate,
+    Brotli,
+}
+
+impl Encoding {
+    /// The coding `Accept-Encoding` rates highest, preferring brotli, then
+    /// gzip, on ties.
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let (mut gzip, mut deflate, mut brotli, mut any) = (None, None, None, None);
+        for accept in headers.get_all(ACCEPT_ENCODING) {
+            for coding in accept.to_str().unwrap_or("").split(',') {
+                let mut parts = coding.split(';').map(str::trim);
+                let name = parts.next().unwrap_or("");
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                match name {
+                    "gzip" | "x-gzip" => gzip = Some(quality),
+                    "deflate" => deflate = Some(quality),
+                    "br" => brotli = Some(quality),
+                    "*" => any = Some(quality),
+                    _ => {}
+                }
+            }
+        }
+        let codings = [
+            (Encoding::Brotli, brotli),
+            (Encoding::Gzip, gzip),
+            (Encoding::Deflate, deflate),
+        ];
+        codings
+            .iter()
+            .fold(
+                (Encoding::Identity, 0.0),
+                |best, &(encoding, quality)| match quality.or(any).unwrap_or(0.0) {
+                    quality if quality > best.1 => (encoding, quality),
+                    _ => best,
+                },
+            )
+            .0
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    fn encode(self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match StreamEncoder::new(self) {
+            Some(mut encoder) => {
+                encoder.writer().write_all(body)?;
+                encoder.finish()
+            }
+            None => Ok(body.to_vec()),
+        }
+    }
+}
+
+/// Compresses a body as it arrives, flushing after each chunk so a client
+/// reading a stream sees every value when it is sent.
+enum StreamEncoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl StreamEncoder {
+    fn new(encoding: Encoding) -> Option<Self> {
+        match encoding {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some(StreamEncoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                Compression::default(),
+            ))),
+            Encoding::Deflate => Some(StreamEncoder::Deflate(ZlibEncoder::new(
+                Vec::new(),
+                Compression::default(),
+            ))),
+            Encoding::Brotli => Some(StreamEncoder::Brotli(Box::new(
+                brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22),
+            ))),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            StreamEncoder::Gzip(encoder) => encoder,
+            StreamEncoder::Deflate(encoder) => encoder,
+            StreamEncoder::Brotli(encoder) => &mut **encoder,
+        }
+    }
+
+    /// Encodes `chunk`, returning what is ready to send.
+    fn encode(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        self.writer().write_all(chunk)?;
+        self.writer().flush()?;
+        Ok(mem::take(match self {
+            StreamEncoder::Gzip(encoder) => encoder.get_mut(),
+            StreamEncoder::Deflate(encoder) => encoder.get_mut(),
+            StreamEncoder::Brotli(encoder) => encoder.get_mut(),
+        }))
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            StreamEncoder::Gzip(encoder) => encoder.finish(),
+            StreamEncoder::Deflate(encoder) => encoder.finish(),
+            StreamEncoder::Brotli(mut encoder) => {
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+/// Compresses a complete response body of at least `min_bytes`, and streamed
+/// bodies, whose length is unknown, chunk by chunk. Any response that could
+/// have been compressed says it varies by `Accept-Encoding`.
+async fn compress(
+    resp: Response<Body>,
+    encoding: Encoding,
+    min_bytes: usize,
+) -> Result<Response<Body>, hyper::Error> {
+    let length = HttpBody::size_hint(resp.body()).exact();
+    if length.is_some_and(|len| len < min_bytes as u64)
+        || resp.headers().contains_key(CONTENT_ENCODING)
+        || resp.status() == StatusCode::SWITCHING_PROTOCOLS
+    {
+        return Ok(resp);
+    }
+    let (mut parts, body) = resp.into_parts();
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    let encoder = match StreamEncoder::new(encoding) {
+        Some(encoder) => encoder,
+        None => return Ok(Response::from_parts(parts, body)),
+    };
+    parts
+        .headers
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    if length.is_none() {
+        let encoded = stream::unfold(Some((body, encoder)), |stream| async move {
+            let (mut body, mut encoder) = stream?;
+            match body.data().await {
+                Some(Ok(chunk)) => Some((encoder.encode(&chunk), Some((body, encoder)))),
+                Some(Err(err)) => Some((Err(io::Error::other(err)), None)),
+                None => Some((encoder.finish(), None)),
+            }
+        });
+        return Ok(Response::from_parts(parts, Body::wrap_stream(encoded)));
+    }
+    let body = hyper::body::to_bytes(body).await?;
+    Ok(match encoding.encode(&body) {
+        Ok(encoded) => {
+            parts.headers.remove(CONTENT_LENGTH);
+            Response::from_parts(parts, encoded.into())
+        }
+        Err(err) => {
+            warn!("Cannot {} encode response: {}", encoding.name(), err);
+            parts.headers.remove(CONTENT_ENCODING);
+            Response::from_parts(parts, body.into())
+        }
+    })
+}
+
+#[derive(Clone)]
+pub struct RandomOptions {
+    format: Format,
+    envelope: bool,
+    typed: bool,
+    count: Option<usize>,
+    /// Bins of the histogram returned instead of the values, with `histogram`
+    /// or `stats`.
+    bins: Option<usize>,
+    stats: bool,
+    scale: f64,
+    offset: f64,
+    fixed_point: bool,
+    pretty: bool,
+    /// Generate from a fresh generator seeded with this value instead of the
+    /// server's, so the same `seed` and `index` always give the same draw.
+    seed: Option<u64>,
+    stream: Option<String>,
+    entropy_source: EntropySource,
+    /// Draws discarded before generating: `index` for a request `seed`, or
+    /// `burnin` with seeded RNG modes, since skipping values of `thread_rng`
+    /// or `os_rng` changes nothing observable.
+    burnin: usize,
+    jitter: f64,
+    assert_min: Option<f64>,
+    assert_max: Option<f64>,
+    timing: bool,
+    show_uniform: bool,
+    with_density: bool,
+    /// Return `[value, antithetic_value]` drawn from `u` and `1 - u`.
+    antithetic: bool,
+    /// The client's `X-Request-Id`, or a random one, for the audit log.
+    request_id: String,
+    smooth: bool,
+    /// Standard deviation of the Gaussian noise added to each draw for a
+    /// smoothed bootstrap, set by `smoothed` once the request is known.
+    bandwidth: Option<f64>,
+}
+
+#[derive(Debug)]
+struct AssertionFailed {
+    distribution: &'static str,
+    value: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Display for AssertionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} generated {} outside the asserted range [{}, {}]",
+            self.distribution, self.value, self.min, self.max
+        )
+    }
+}
+
+impl std::error::Error for AssertionFailed {}
+
+#[derive(Debug)]
+struct NoDensity {
+    distribution: &'static str,
+}
+
+impl Display for NoDensity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "density is not implemented for {}", self.distribution)
+    }
+}
+
+impl std::error::Error for NoDensity {}
+
+#[derive(Debug)]
+struct SamplingFailed {
+    distribution: &'static str,
+    attempts: usize,
+}
+
+impl Display for SamplingFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} found no acceptable value in {} attempts",
+            self.distribution, self.attempts
+        )
+    }
+}
+
+impl std::error::Error for SamplingFailed {}
+
+fn query_param<T>(query: &Value, name: &str) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match query[name].as_str() {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| format_err!("invalid {} {:?}: {}", name, value, err)),
+        None => Ok(None),
+    }
+}
+
+impl RandomOptions {
+    pub fn parse(
+        query: &Value,
+        headers: &HeaderMap,
+        config: &Config,
+        typed: bool,
+    ) -> Result<Self, Error> {
+        let format = Format::negotiate(query, headers)?;
+        if let Some(ref formats) = config.formats {
+            if !formats.iter().any(|name| name == format.name()) {
+                bail!("format {} is not enabled", format.name());
+            }
+        }
+        let envelope = query_param(query, "envelope")?.unwrap_or(config.envelope);
+        let count = query_param::<usize>(query, "count")?;
+        let stats = query_param(query, "stats")?.unwrap_or(false);
+        let bins = if stats || query_param(query, "histogram")?.unwrap_or(false) {
+            let bins = query_param::<i64>(query, "bins")?.unwrap_or(10);
+            if bins <= 0 {
+                bail!("bins must be positive, got {}", bins);
+            }
+            if bins as u64 > config.max_bins as u64 {
+                bail!("bins {} exceeds the maximum of {}", bins, config.max_bins);
+            }
+            Some(bins as usize)
+        } else {
+            None
+        };
+        let max_count = if bins.is_some() {
+            config.max_histogram_count
+        } else {
+            config.max_count
+        };
+        match count {
+            Some(0) => bail!("count must be positive"),
+            Some(count) if count > max_count => {
+                bail!("count {} exceeds the maximum of {}", count, max_count)
+            }
+            _ => {}
+        }
+        let seed = query_param(query, "seed")?;
+        let (name, skip) = match (
+            seed,
+            query_param(query, "index")?,
+            query_param(query, "burnin")?,
+        ) {
+            (None, Some(_), _) => bail!("index requires seed"),
+            (Some(_), _, Some(_)) => bail!("burnin cannot be combined with seed, use index"),
+            (Some(_), index, None) => ("index", index.unwrap_or(0)),
+            (None, None, burnin) => ("burnin", burnin.unwrap_or(0)),
+        };
+        if skip > config.max_burnin {
+            bail!(
+                "{} {} exceeds the maximum of {}",
+                name,
+                skip,
+                config.max_burnin
+            );
+        }
+        let stream = query_param::<String>(query, "stream")?;
+        match stream {
+            Some(_) if seed.is_some() => bail!("stream cannot be combined with seed"),
+            Some(ref name)
+                if name.is_empty()
+                    || name.len() > 64
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                bail!("stream names must be 1-64 letters, digits, '-' or '_'")
+            }
+            _ => {}
+        }
+        let entropy_source = match (
+            query_param(query, "entropy_source")?,
+            query_param(query, "rng")?,
+        ) {
+            (Some(_), Some(_)) => bail!("give either entropy_source or rng, not both"),
+            (entropy_source, rng) => entropy_source.or(rng),
+        };
+        if entropy_source.is_some() && (seed.is_some() || stream.is_some()) {
+            bail!("entropy_source cannot be combined with seed or stream");
+        }
+        let entropy_source = entropy_source.unwrap_or(config.entropy_source);
+        let unseeded = match entropy_source {
+            EntropySource::ThreadRng => config.rng_mode == RngMode::ThreadLocal,
+            EntropySource::OsRng => true,
+            EntropySource::Chacha | EntropySource::Pcg => false,
+        };
+        let burnin = if seed.is_none() && stream.is_none() && unseeded {
+            0
+        } else {
+            skip
+        };
+        let timing = query_param(query, "timing")?.unwrap_or(false);
+        if timing && bins.is_none() && (!envelope || count.is_some()) {
+            bail!("timing requires an enveloped single value or histogram response");
+        }
+        let show_uniform = query_param(query, "show_uniform")?.unwrap_or(false);
+        if show_uniform && (!envelope || count.is_some() || bins.is_some()) {
+            bail!("show_uniform requires an enveloped single value response");
+        }
+        let with_density = query_param(query, "with_density")?.unwrap_or(false);
+        if with_density && (!envelope || count.is_some() || bins.is_some()) {
+            bail!("with_density requires an enveloped single value response");
+        }
+        let request_id = request_id(headers).to_str().unwrap().to_owned();
+        let antithetic = query_param(query, "antithetic")?.unwrap_or(false);
+        if antithetic && (count.is_some() || bins.is_some()) {
+            bail!("antithetic cannot be combined with count or bins");
+        }
+        if antithetic && with_density {
+            bail!("with_density cannot be combined with antithetic");
+        }
+        let assert_min = query_param(query, "assert_min")?;
+        let assert_max = query_param(query, "assert_max")?;
+        if let (Some(min), Some(max)) = (assert_min, assert_max) {
+            if min > max {
+                bail!("assert_min {} exceeds assert_max {}", min, max);
+            }
+        }
+        let scale = query_param(query, "scale")?.unwrap_or(1.0);
+        let offset = query_param(query, "offset")?.unwrap_or(0.0);
+        let jitter = query_param(query, "jitter")?.unwrap_or(0.0);
+        for &(name, value) in &[("scale", scale), ("offset", offset), ("jitter", jitter)] {
+            check_magnitude(name, value, config.max_param_magnitude)?;
+        }
+        if jitter < 0.0 {
+            bail!("jitter must be non-negative");
+        }
+        if with_density && (scale != 1.0 || offset != 0.0 || jitter != 0.0) {
+            bail!("with_density cannot be combined with scale, offset or jitter");
+        }
+        Ok(RandomOptions {
+            format,
+            envelope,
+            typed,
+            count,
+            bins,
+            stats,
+            scale,
+            offset,
+            fixed_point: query_param(query, "fixed_point")?.unwrap_or(config.fixed_point),
+            pretty: query_param(query, "pretty")?.unwrap_or(false),
+            seed,
+            stream,
+            burnin,
+            jitter,
+            assert_min,
+            assert_max,
+            timing,
+            show_uniform,
+            with_density,
+            antithetic,
+            entropy_source,
+            request_id,
+            smooth: query_param(query, "smooth")?.unwrap_or(false),
+            bandwidth: None,
+        })
+    }
+
+    fn serialize<T: Text>(&self, resp: &T) -> Result<Vec<u8>, Error> {
+        match self.format {
+            Format::Json if self.fixed_point && self.pretty => {
+                to_json_with(resp, FixedPointFormatter(PrettyFormatter::new()))
+            }
+            Format::Json if self.fixed_point => {
+                to_json_with(resp, FixedPointFormatter(CompactFormatter))
+            }
+            Format::Json if self.pretty => Ok(serde_json::to_vec_pretty(resp)?),
+            format => serialize(format, resp),
+        }
+    }
+
+    fn serialize_single(&self, resp: &RngResponse) -> Result<Vec<u8>, Error> {
+        if self.envelope {
+            self.serialize(resp)
+        } else {
+            self.serialize(&resp.value)
+        }
+    }
+
+    /// Runs `f` on the generator this request selected; `draws` is the number
+    /// of samples it takes, counted against a named stream.
+    fn with_rng<T>(
+        &self,
+        state: &State,
+        draws: usize,
+        f: impl FnOnce(&mut dyn RngCore) -> T,
+    ) -> Result<T, Error> {
+        match (self.seed, &self.stream) {
+            (Some(seed), _) => Ok(f(&mut StdRng::seed_from_u64(seed))),
+            (None, Some(name)) => state.with_stream(name, self.burnin + draws, f),
+            (None, None) => state.with_rng(self.entropy_source, f),
+        }
+    }
+
+    /// The seed that replays this request: its own `seed`, or the server's
+    /// when the generator it draws from is seeded from it.
+    fn replay_seed(&self, state: &State) -> Option<u64> {
+        let seeded = match self.entropy_source {
+            EntropySource::ThreadRng => state.config().rng_mode != RngMode::ThreadLocal,
+            EntropySource::OsRng => false,
+            EntropySource::Chacha | EntropySource::Pcg => true,
+        };
+        match self.seed {
+            Some(seed) => Some(seed),
+            None if seeded || self.stream.is_some() => Some(state.seed),
+            None => None,
+        }
+    }
+
+    fn burn_in<R: Rng + ?Sized>(&self, request: &RngRequest, rng: &mut R) -> Result<(), Error> {
+        for _ in 0..self.burnin {
+            sample(request, rng)?;
+        }
+        Ok(())
+    }
+
+    fn smoothed(&self, request: &RngRequest) -> Result<RandomOptions, Error> {
+        match request.bandwidth() {
+            Some(bandwidth) => Ok(RandomOptions {
+                bandwidth: Some(bandwidth),
+                ..self.clone()
+            }),
+            None => bail!("smooth only applies to empirical, not {}", request.name()),
+        }
+    }
+
+    fn draw<R: Rng + ?Sized>(&self, request: &RngRequest, rng: &mut R) -> Result<Sample, Error> {
+        let mut value = sample(request, rng)?;
+        if let Some(bandwidth) = self.bandwidth {
+            value = value.map(|value| value + bandwidth * rng.sample(StandardNormal));
+        }
+        Ok(self.transform(value, rng))
+    }
+
+    fn transform<R: Rng + ?Sized>(&self, value: Sample, rng: &mut R) -> Sample {
+        let value = value.map(|value| value * self.scale + self.offset);
+        if self.jitter > 0.0 {
+            let noise = Uniform::new_inclusive(-self.jitter, self.jitter);
+            value.map(|value| value + rng.sample(noise))
+        } else {
+            value
+        }
+    }
+
+    fn check(&self, request: &RngRequest, sample: &Sample) -> Result<(), Error> {
+        let min = self.assert_min.unwrap_or(f64::NEG_INFINITY);
+        let max = self.assert_max.unwrap_or(f64::INFINITY);
+        let values = match sample {
+            Sample::Scalar(value) => slice::from_ref(value),
+            Sample::Vector(values) => values,
+            Sample::Text(_) => &[],
+        };
+        match values.iter().find(|&&value| value < min || value > max) {
+            Some(&value) => Err(AssertionFailed {
+                distribution: request.name(),
+                value,
+                min,
+                max,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A parameter of the wrong JSON type, recovered from serde's error message
+/// and the path `serde_path_to_error` tracked to it.
+#[derive(Debug)]
+struct InvalidType {
+    field: String,
+    expected: String,
+    got: String,
+}
+
+impl InvalidType {
+    fn from_serde(path: &serde_path_to_error::Path, err: &serde_json::Error) -> Option<Self> {
+        let message = err.to_string();
+        let (message, value) = match message.strip_prefix("invalid value: ") {
+            Some(message) => (message, true),
+            None => (message.strip_prefix("invalid type: ")?, false),
+        };
+        let (got, expected) = message.split_at(message.rfind(", expected ")?);
+        let expected = match &expected[", expected ".len()..] {
+            "f64" | "f32" => "number",
+            "u64" | "u32" | "usize" => "non-negative integer",
+            "i64" | "i32" => "integer",
+            "a sequence" => "array",
+            expected => expected,
+        };
+        let got = match got.split(' ').next()? {
+            _ if value => got.replace('`', ""),
+            "floating" => "float".to_owned(),
+            "sequence" => "array".to_owned(),
+            "map" => "object".to_owned(),
+            got => got.to_owned(),
+        };
+        let path = path.to_string();
+        Some(InvalidType {
+            field: path.strip_prefix("parameters.").unwrap_or(&path).to_owned(),
+            expected: expected.to_owned(),
+            got,
+        })
+    }
+}
+
+impl Display for InvalidType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "field '{}' expected {}, got {}",
+            self.field, self.expected, self.got
+        )
+    }
+}
+
+impl std::error::Error for InvalidType {}
+
+pub fn parse_request(body: &[u8], config: &Config) -> Result<RngRequest, Error> {
+    deserialize_request(serde_json::from_slice(body)?, config)
+}
+
+/// Takes a `count`, `seed` and `rng` out of a `/random` request body,
+/// returning the query with them added so they are checked exactly like
+/// `?count=`, `?seed=` and `?rng=`.
+fn body_options(request: &mut Value, query: &Value) -> Result<Option<Value>, Error> {
+    let mut options = match query {
+        Value::Object(query) => query.clone(),
+        _ => serde_json::Map::new(),
+    };
+    let mut changed = false;
+    for name in ["count", "seed", "rng", "stats", "bins"] {
+        let value = match request
+            .as_object_mut()
+            .and_then(|request| request.remove(name))
+        {
+            Some(value) => value,
+            None => continue,
+        };
+        if !query[name].is_null() {
+            bail!("{} cannot be given in both the query and the body", name);
+        }
+        let value = match value {
+            Value::String(rng) if name == "rng" => rng,
+            _ if name == "rng" => bail!("rng must be a string"),
+            Value::Bool(stats) if name == "stats" => stats.to_string(),
+            _ if name == "stats" => bail!("stats must be a boolean"),
+            value => value
+                .as_u64()
+                .ok_or_else(|| format_err!("{} must be a non-negative integer", name))?
+                .to_string(),
+        };
+        options.insert(name.to_owned(), Value::String(value));
+        changed = true;
+    }
+    Ok(if changed {
+        Some(Value::Object(options))
+    } else {
+        None
+    })
+}
+
+/// Regenerates the sequence a `VerifyRequest` describes and compares checksums.
+fn verify(body: &[u8], state: &State) -> Result<VerifyResponse, Error> {
+    let claim = serde_json::from_slice::<VerifyRequest>(body)?;
+    let request = deserialize_request(claim.request, &state.config())?;
+    let query = serde_json::json!({
+        "seed": claim.seed.to_string(),
+        "count": claim.count.to_string(),
+    });
+    let options = RandomOptions::parse(&query, &HeaderMap::new(), &state.config(), false)?;
+    let checksum = format!("{:016x}", fnv1a(&generate(&request, &options, state)?));
+    Ok(VerifyResponse {
+        verified: checksum.eq_ignore_ascii_case(claim.checksum.trim()),
+        checksum,
+    })
+}
+
+/// Parses a body holding only the parameters of `distribution`, as sent to
+/// `/random/{distribution}`; an empty body leaves every parameter defaulted.
+fn parse_parameters(body: &[u8], distribution: &str, config: &Config) -> Result<RngRequest, Error> {
+    let parameters = if body.iter().all(u8::is_ascii_whitespace) {
+        Value::Object(Default::default())
+    } else {
+        serde_json::from_slice(body)?
+    };
+    let mut request = serde_json::Map::new();
+    request.insert("distribution".into(), distribution.into());
+    request.insert("parameters".into(), parameters);
+    deserialize_request(Value::Object(request), config)
+}
+
+/// Splits a `GET /random` query into the request it names with `distribution`
+/// and that distribution's parameters, and the query left for the options.
+/// A parameter shadows an option of the same name, such as gamma's `scale`.
+fn query_request(query: &Value, config: &Config) -> Result<(RngRequest, Value), Error> {
+    let mut options = match query {
+        Value::Object(query) => query.clone(),
+        _ => serde_json::Map::new(),
+    };
+    let name = match options.remove("distribution") {
+        Some(Value::String(name)) => name,
+        Some(_) => bail!("distribution must be a string"),
+        None => bail!("missing distribution"),
+    };
+    let examples = RngRequest::defaults()
+        .into_iter()
+        .filter(|known| known.name() == name)
+        .map(|known| serde_json::to_value(known).unwrap())
+        .collect::<Vec<_>>();
+    if examples.is_empty() {
+        bail!("unknown distribution {:?}", name);
+    }
+    let mut parameters = serde_json::Map::new();
+    for example in &examples {
+        for field in example["parameters"]
+            .as_object()
+            .into_iter()
+            .flat_map(|p| p.keys())
+        {
+            if let Some(value) = options.remove(field) {
+                // Query values arrive as strings; anything that reads as JSON,
+                // such as numbers and arrays, is passed on as that instead.
+                let value = match value {
+                    Value::String(text) => {
+                        serde_json::from_str(&text).unwrap_or(Value::String(text))
+                    }
+                    value => value,
+                };
+                parameters.insert(field.clone(), value);
+            }
+        }
+    }
+    let request = serde_json::json!({"distribution": name, "parameters": parameters});
+    Ok((
+        deserialize_request(request, config)?,
+        Value::Object(options),
+    ))
+}
+
+/// Lets requests, including the components of a mixture, omit `parameters`
+/// when every parameter has a default.
+fn default_parameters(request: &mut Value) {
+    if let Some(request) = request.as_object_mut() {
+        let parameters = request
+            .entry("parameters")
+            .or_insert_with(|| Value::Object(Default::default()));
+        let components = parameters
+            .get_mut("components")
+            .and_then(Value::as_array_mut);
+        for component in components.into_iter().flatten() {
+            if let Some(request) = component.get_mut("request") {
+                default_parameters(request);
+            }
+        }
+    }
+}
+
+fn deserialize_request(mut request: Value, config: &Config) -> Result<RngRequest, Error> {
+    default_parameters(&mut request);
+    let mut request =
+        serde_path_to_error::deserialize::<_, RngRequest>(request).map_err(|err| {
+            match InvalidType::from_serde(err.path(), err.inner()) {
+                Some(invalid) => invalid.into(),
+                None => Error::from(err.into_inner()),
+            }
+        })?;
+    request.enforce(config)?;
+    Ok(request)
+}
+
+/// Serves a JSON body that only changes between releases, tagged with a hash
+/// of its contents so clients repeating the tag in `If-None-Match` get 304.
+fn respond_cached(headers: &HeaderMap, body: Vec<u8>) -> Response<Body> {
+    let etag = format!("\"{:016x}\"", fnv1a(&body));
+    let matched = headers
+        .get_all(IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    let resp = Response::builder().header(ETAG, etag.as_str());
+    if matched {
+        resp.status(StatusCode::NOT_MODIFIED).body(Body::empty())
+    } else {
+        resp.header(CONTENT_TYPE, Format::Json.content_type())
+            .body(body.into())
+    }
+    .unwrap()
+}
+
+fn respond(format: Format, body: Vec<u8>) -> Response<Body> {
+    let mut resp = Response::builder().header(CONTENT_TYPE, format.content_type());
+    if let Format::Csv = format {
+        resp = resp.header(CONTENT_DISPOSITION, "attachment; filename=\"samples.csv\"");
+    }
+    resp.body(body.into()).unwrap()
+}
+
+pub fn handle_request(
+    request: &RngRequest,
+    options: &RandomOptions,
+    state: &State,
+) -> Result<RngResponse, Error> {
+    if options.antithetic && request.inverse_transform().is_none() {
+        bail!(
+            "antithetic pairs need a distribution sampled by inverse transform, which {} is not",
+            request.name()
+        );
+    }
+    let (value, uniform) = options.with_rng(state, 1, |rng| {
+        options.burn_in(request, rng)?;
+        match request.inverse_transform() {
+            Some(inverse) if options.antithetic => {
+                let uniform = rng.gen::<f64>();
+                let pair = Sample::Vector(vec![inverse(uniform), inverse(1.0 - uniform)]);
+                let uniform = if options.show_uniform {
+                    Some(uniform)
+                } else {
+                    None
+                };
+                Ok((options.transform(pair, rng), uniform))
+            }
+            Some(inverse) if options.show_uniform => {
+                let uniform = rng.gen::<f64>();
+                let value = options.transform(Sample::Scalar(inverse(uniform)), rng);
+                Ok::<_, Error>((value, Some(uniform)))
+            }
+            _ => Ok((options.draw(request, rng)?, None)),
+        }
+    })??;
+    Ok(RngResponse {
+        value,
+        kind: None,
+        uniform,
+        density: None,
+        generation_ns: None,
+    })
+}
+
+/// Summarizes `count` draws in two passes over the same seed, the first for
+/// the moments and range and the second for the histogram, so no batch is
+/// held in memory.
+fn summarize(
+    request: &RngRequest,
+    options: &RandomOptions,
+    count: usize,
+    bins: usize,
+    state: &State,
+) -> Result<SummaryResponse, Error> {
+    let draw = |rng: &mut StdRng| {
+        let sample = options.draw(request, rng)?;
+        options.check(request, &sample)?;
+        sample
+            .scalar()
+            .ok_or_else(|| format_err!("histogram requires a scalar distribution"))
+    };
+    let seed = options.with_rng(state, count, |rng| {
+        options.burn_in(request, rng)?;
+        Ok::<_, Error>(rng.gen::<u64>())
+    })??;
+    let mut rng = StdRng::seed_from_u64(seed);
+    // Welford's update keeps the variance accurate for large means.
+    let (mut mean, mut squares) = (0.0, 0.0);
+    let (mut low, mut high) = (f64::INFINITY, f64::NEG_INFINITY);
+    for n in 1..=count {
+        let value = draw(&mut rng)?;
+        let delta = value - mean;
+        mean += delta / n as f64;
+        squares += delta * (value - mean);
+        low = low.min(value);
+        high = high.max(value);
+    }
+    let (min, max) = if low < high {
+        (low, high)
+    } else {
+        (low - 0.5, high + 0.5)
+    };
+    let width = (max - min) / bins as f64;
+    let mut counts = vec![0; bins];
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..count {
+        let value = draw(&mut rng)?;
+        let bin = (((value - min) / width) as usize).min(bins - 1);
+        counts[bin] += 1;
+    }
+    let edges = (0..=bins).map(|i| min + width * i as f64).collect();
+    Ok(SummaryResponse {
+        count,
+        mean,
+        variance: squares / (count.max(2) - 1) as f64,
+        min: low,
+        max: high,
+        histogram: HistogramResponse {
+            edges,
+            counts,
+            generation_ns: None,
+        },
+        generation_ns: None,
+    })
+}
+
+/// `count` samples, as `/random?count=` and gRPC `Batch` return them.
+fn batch(
+    request: &RngRequest,
+    options: &RandomOptions,
+    count: usize,
+    state: &State,
+) -> Result<Vec<Sample>, Error> {
+    let bytes = count.saturating_mul(request.sample_bytes());
+    if bytes > state.config().max_batch_bytes {
+        bail!(
+            "a batch of {} {} samples needs about {} bytes, exceeding the maximum of {}",
+            count,
+            request.name(),
+            bytes,
+            state.config().max_batch_bytes
+        );
+    }
+    let values = options.with_rng(state, count, |rng| {
+        options.burn_in(request, rng)?;
+        (0..count)
+            .map(|_| options.draw(request, rng))
+            .collect::<Result<Vec<_>, Error>>()
+    })??;
+    for value in &values {
+        options.check(request, value)?;
+    }
+    Ok(values)
+}
+
+fn generate(
+    request: &RngRequest,
+    options: &RandomOptions,
+    state: &State,
+) -> Result<Vec<u8>, Error> {
+    let smoothed;
+    let options = if options.smooth {
+        smoothed = options.smoothed(request)?;
+        &smoothed
+    } else {
+        options
+    };
+    match (options.count, options.bins) {
+        (count, Some(bins)) => {
+            let start = Instant::now();
+            let mut stats = summarize(request, options, count.unwrap_or(1), bins, state)?;
+            let generation_ns = Some(start.elapsed().as_nanos() as u64).filter(|_| options.timing);
+            if options.stats {
+                stats.generation_ns = generation_ns;
+                state.audit(&options.request_id, request, &stats);
+                return options.serialize(&stats);
+            }
+            let mut resp = stats.histogram;
+            resp.generation_ns = generation_ns;
+            state.audit(&options.request_id, request, &resp);
+            options.serialize(&resp)
+        }
+        (Some(count), None) => {
+            let values = batch(request, options, count, state)?;
+            state.audit(&options.request_id, request, &values);
+            options.serialize(&values)
+        }
+        (None, None) => {
+            let start = Instant::now();
+            let mut resp = handle_request(request, options, state)?;
+            if options.timing {
+                resp.generation_ns = Some(start.elapsed().as_nanos() as u64);
+            }
+            options.check(request, &resp.value)?;
+            if options.with_density {
+                let density = resp.value.scalar().and_then(|value| request.density(value));
+                resp.density = Some(density.ok_or(NoDensity {
+                    distribution: request.name(),
+                })?);
+            }
+            state.audit(&options.request_id, request, &resp.value);
+            if options.typed {
+                resp.kind = Some(request.value_type());
+            }
+            options.serialize_single(&resp)
+        }
+    }
+}
+
+fn body_limit(config: &Config, path: &str) -> usize {
+    config
+        .body_limits
+        .get(path)
+        .cloned()
+        .unwrap_or(config.max_body_bytes)
+}
+
+/// Whether the request body is gzipped, the only `Content-Encoding` accepted
+/// besides `identity`.
+fn request_encoding(headers: &HeaderMap) -> Result<bool, String> {
+    let encoding = match headers.get(CONTENT_ENCODING) {
+        Some(encoding) => encoding.to_str().unwrap_or("").trim().to_ascii_lowercase(),
+        None => return Ok(false),
+    };
+    match encoding.as_str() {
+        "identity" => Ok(false),
+        "gzip" | "x-gzip" => Ok(true),
+        _ => Err(format!(
+            "unsupported request Content-Encoding {:?}",
+            encoding
+        )),
+    }
+}
+
+/// Accepts bodies declared as JSON, or not declared at all.
+fn request_content_type(headers: &HeaderMap) -> Result<(), String> {
+    let content_type = match headers.get(CONTENT_TYPE) {
+        Some(content_type) => content_type.to_str().unwrap_or(""),
+        None => return Ok(()),
+    };
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    if media_type == "application/json" || media_type.ends_with("+json") {
+        Ok(())
+    } else {
+        Err(format!(
+            "unsupported request Content-Type {:?}, expected application/json",
+            content_type
+        ))
+    }
+}
+
+/// Decompresses at most `limit` bytes, returning `None` for bodies that
+/// inflate past it rather than inflating them in full.
+fn gunzip(body: &[u8], limit: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut inflated = Vec::new();
+    GzDecoder::new(body)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut inflated)?;
+    Ok(if inflated.len() > limit {
+        None
+    } else {
+        Some(inflated)
+    })
+}
+
+const NDJSON: &str = "application/x-ndjson";
+
+/// Attached to `/random` responses so the slow request log can say what was
+/// generated.
+struct Generated {
+    distribution: &'static str,
+    count: usize,
+}
+
+enum Socket {
+    Tcp(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Socket {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Socket::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Socket::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Socket {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Socket::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Socket::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Socket::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            Socket::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Socket::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Socket::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            Socket::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+struct Connection {
+    stream: Socket,
+    remote: SocketAddr,
+    write_timeout: Option<Duration>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl Connection {
+    fn tcp(stream: TcpStream, write_timeout: Option<Duration>) -> io::Result<Self> {
+        Ok(Connection {
+            remote: stream.peer_addr()?,
+            stream: Socket::Tcp(stream),
+            write_timeout,
+            deadline: None,
+        })
+    }
+
+    fn tls(stream: TlsStream<TcpStream>, write_timeout: Option<Duration>) -> io::Result<Self> {
+        Ok(Connection {
+            remote: stream.get_ref().0.peer_addr()?,
+            stream: Socket::Tls(Box::new(stream)),
+            write_timeout,
+            deadline: None,
+        })
+    }
+
+    /// Unix peers have no IP address, so they are reported as loopback.
+    fn unix(stream: UnixStream, write_timeout: Option<Duration>) -> io::Result<Self> {
+        Ok(Connection {
+            remote: ([127, 0, 0, 1], 0).into(),
+            stream: Socket::Unix(stream),
+            write_timeout,
+            deadline: None,
+        })
+    }
+
+    fn remote_addr(&self) -> SocketAddr {
+        self.remote
+    }
+
+    fn check_deadline<T>(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        res: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match (res, self.write_timeout) {
+            (Poll::Pending, Some(timeout)) => {
+                let deadline = self
+                    .deadline
+                    .get_or_insert_with(|| Box::pin(time::sleep(timeout)));
+                match deadline.as_mut().poll(cx) {
+                    Poll::Ready(()) => {
+                        warn!(
+                            "Dropping connection from {}: write timed out after {:?}",
+                            self.remote, timeout
+                        );
+                        Poll::Ready(Err(io::ErrorKind::TimedOut.into()))
+                    }
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+            (res, _) => {
+                self.deadline = None;
+                res
+            }
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.stream).poll_write(cx, buf);
+        this.check_deadline(cx, res)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.stream).poll_flush(cx);
+        this.check_deadline(cx, res)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+fn bind(addr: &SocketAddr, dual_stack: bool, reuse_port: bool) -> io::Result<TcpListener> {
+    let builder = match addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        SocketAddr::V6(v6) => {
+            let builder = TcpBuilder::new_v6()?;
+            if v6.ip().is_unspecified() {
+                builder.only_v6(!dual_stack)?;
+            }
+            builder
+        }
+    };
+    builder.reuse_address(true)?.reuse_port(reuse_port)?;
+    let listener = builder.bind(addr)?.listen(1024)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+fn tls_config(tls: &TlsConfig) -> Result<ServerConfig, Error> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| format_err!("cannot open {}: {}", path.display(), err))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&tls.cert)?)
+        .map_err(|_| format_err!("invalid certificate in {}", tls.cert.display()))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut open(&tls.key)?)
+        .map_err(|_| format_err!("invalid private key in {}", tls.key.display()))?;
+    if keys.is_empty() {
+        keys = rustls_pemfile::rsa_private_keys(&mut open(&tls.key)?)
+            .map_err(|_| format_err!("invalid private key in {}", tls.key.display()))?;
+    }
+    let key = keys
+        .into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or_else(|| format_err!("no private key found in {}", tls.key.display()))?;
+    let verifier = match tls.client_ca {
+        Some(ref path) => {
+            let mut roots = RootCertStore::empty();
+            let certs = rustls_pemfile::certs(&mut open(path)?).unwrap_or_default();
+            match roots.add_parsable_certificates(&certs) {
+                (0, _) => bail!("no valid CA certificates found in {}", path.display()),
+                _ => AllowAnyAuthenticatedClient::new(roots).boxed(),
+            }
+        }
+        None => NoClientAuth::boxed(),
+    };
+    let suites = match tls.cipher_suites {
+        Some(ref names) => names
+            .iter()
+            .map(|name| {
+                ALL_CIPHER_SUITES
+                    .iter()
+                    .find(|suite| format!("{:?}", suite.suite()) == *name)
+                    .copied()
+                    .ok_or_else(|| format_err!("unknown cipher suite {}", name))
+            })
+            .collect::<Result<Vec<_>, Error>>()?,
+        None => ALL_CIPHER_SUITES.to_vec(),
+    };
+    let mut versions = match tls.min_version {
+        TlsVersion::Tls12 => vec![&version::TLS12, &version::TLS13],
+        TlsVersion::Tls13 => vec![&version::TLS13],
+    };
+    versions.retain(|version| {
+        suites
+            .iter()
+            .any(|suite| suite.version().version == version.version)
+    });
+    if versions.is_empty() {
+        bail!("no configured cipher suite supports a TLS version at or above min_version");
+    }
+    let config = ServerConfig::builder()
+        .with_cipher_suites(&suites)
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&versions)?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs, key)?;
+    Ok(config)
+}
+
+fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if StdUnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another server", path.display()),
+            ));
+        }
+        info!("Removing stale socket: {}", path.display());
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+async fn shutdown_signal() {
+    let signals = signal(SignalKind::interrupt())
+        .and_then(|interrupt| Ok((interrupt, signal(SignalKind::terminate())?)));
+    let (mut interrupt, mut terminate) = match signals {
+        Ok(signals) => signals,
+        Err(err) => {
+            warn!("Cannot listen for signals: {}", err);
+            return future::pending().await;
+        }
+    };
+    let name = tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    };
+    info!("Received signal {}, shutting down", name);
+}
+
+/// Prints 32 bytes from the operating system's generator, or writes them to a
+/// new file at `output` that only its owner can read.
+fn generate_key(encoding: ByteEncoding, output: Option<&str>) -> Result<(), Error> {
+    let mut key = [0; 32];
+    OsRng::new()?.fill_bytes(&mut key);
+    let key = encoding.encode(&key);
+    match output {
+        Some(path) => {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)
+                .map_err(|err| format_err!("cannot create {}: {}", path, err))?;
+            writeln!(file, "{}", key)?;
+        }
+        None => println!("{}", key),
+    }
+    Ok(())
+}
+
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_TLS_HANDSHAKES: usize = 64;
+
+/// How long each `bench` row runs, on how many threads, and what each call
+/// generates.
+struct BenchPlan {
+    samples: usize,
+    duration: Option<Duration>,
+    concurrency: usize,
+    batch: usize,
+    formats: Vec<String>,
+}
+
+impl BenchPlan {
+    fn from_args(matches: &ArgMatches) -> Result<Self, Error> {
+        let number = |name: &str| -> Result<usize, Error> {
+            let value = matches.value_of(name).unwrap();
+            match value.parse() {
+                Ok(0) | Err(_) => bail!("{} must be a positive integer, not {:?}", name, value),
+                Ok(number) => Ok(number),
+            }
+        };
+        let duration = match matches.value_of("duration") {
+            Some(seconds) => {
+                let duration = seconds.parse().ok().and_then(|seconds| {
+                    Duration::try_from_secs_f64(seconds)
+                        .ok()
+                        .filter(|duration| !duration.is_zero())
+                });
+                Some(duration.ok_or_else(|| {
+                    format_err!("duration must be a positive number, not {:?}", seconds)
+                })?)
+            }
+            None => None,
+        };
+        Ok(BenchPlan {
+            samples: number("samples")?,
+            duration,
+            concurrency: number("concurrency")?,
+            batch: number("batch")?,
+            formats: matches
+                .values_of("format")
+                .unwrap()
+                .map(str::to_owned)
+                .collect(),
+        })
+    }
+}
+
+/// Prints a table of how fast `generate` produces and serializes `request`,
+/// given as a distribution name, using its default parameters, or as a JSON
+/// request, or every distribution when it is not given, in each format.
+fn bench(config: Config, request: Option<&str>, plan: &BenchPlan) -> Result<(), Error> {
+    let requests = match request {
+        Some(request) if request.trim_start().starts_with('{') => {
+            vec![parse_request(request.as_bytes(), &config)?]
+        }
+        Some(request) => {
+            let known = RngRequest::defaults()
+                .into_iter()
+                .find(|known| known.name() == request)
+                .ok_or_else(|| format_err!("unknown distribution {}", request))?;
+            vec![known]
+        }
+        None => RngRequest::defaults(),
+    };
+    let state = State::new(config)?;
+    let calls = plan.samples.div_ceil(plan.batch * plan.concurrency);
+    println!(
+        "{:<24} {:<8} {:>6} {:>11} {:>14} {:>10}",
+        "distribution", "format", "batch", "concurrency", "samples/sec", "MB/sec"
+    );
+    for request in &requests {
+        for format in &plan.formats {
+            let mut query = serde_json::json!({ "format": format });
+            if plan.batch > 1 {
+                query["count"] = plan.batch.to_string().into();
+            }
+            let res = RandomOptions::parse(&query, &HeaderMap::new(), &state.config(), false)
+                .and_then(|options| {
+                    let start = Instant::now();
+                    let worker = || -> Result<(usize, usize), Error> {
+                        let (mut done, mut bytes) = (0, 0);
+                        while match plan.duration {
+                            Some(duration) => start.elapsed() < duration,
+                            None => done < calls,
+                        } {
+                            bytes += hint::black_box(generate(request, &options, &state)?).len();
+                            done += 1;
+                        }
+                        Ok((done, bytes))
+                    };
+                    let totals = thread::scope(|scope| {
+                        let workers = (0..plan.concurrency)
+                            .map(|_| scope.spawn(worker))
+                            .collect::<Vec<_>>();
+                        workers
+                            .into_iter()
+                            .map(|worker| worker.join().unwrap())
+                            .try_fold((0, 0), |(done, bytes), totals| {
+                                totals.map(|(more, more_bytes)| (done + more, bytes + more_bytes))
+                            })
+                    })?;
+                    Ok((totals, start.elapsed()))
+                });
+            match res {
+                Ok(((done, bytes), elapsed)) => println!(
+                    "{:<24} {:<8} {:>6} {:>11} {:>14.0} {:>10.1}",
+                    request.name(),
+                    format,
+                    plan.batch,
+                    plan.concurrency,
+                    (done * plan.batch) as f64 / elapsed.as_secs_f64(),
+                    bytes as f64 / 1e6 / elapsed.as_secs_f64()
+                ),
+                Err(err) => println!("{:<24} {:<8} {}", request.name(), format, err),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn self_test() -> bool {
+    let mut rng = rand::thread_rng();
+    let mut passed = true;
+    for request in RngRequest::defaults() {
+        let res = request.validate().and_then(|()| {
+            panic::catch_unwind(AssertUnwindSafe(|| sample(&request, &mut rng)))
+                .map_err(|_| format_err!("panicked"))?
+        });
+        match res {
+            Ok(Sample::Scalar(value)) if value.is_finite() => {
+                println!("{}: ok ({})", request.name(), value)
+            }
+            Ok(Sample::Vector(ref values)) if values.iter().all(|value| value.is_finite()) => {
+                println!("{}: ok ({:?})", request.name(), values)
+            }
+            Ok(Sample::Text(ref text)) => println!("{}: ok ({})", request.name(), text),
+            Ok(sample) => {
+                println!(
+                    "{}: FAILED (non-finite sample {})",
+                    request.name(),
+                    sample.text()
+                );
+                passed = false;
+            }
+            Err(err) => {
+                println!("{}: FAILED ({})", request.name(), err);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+/// The service answering one connection from `remote`, with request ids,
+/// access logging and metrics, for embedding the routes in another server.
+pub fn service(
+    state: Arc<State>,
+    remote: SocketAddr,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = hyper::Error,
+    Future = impl Future<Output = Result<Response<Body>, hyper::Error>> + Send,
+> + Clone {
+    logged(state, remote, router::microservice_handler)
+}
+
+fn logged<F>(
+    state: Arc<State>,
+    remote: SocketAddr,
+    handler: fn(Request<Body>, Arc<State>, SocketAddr) -> F,
+) -> impl Service<
+    Request<Body>,
+    Response = Response<Body>,
+    Error = hyper::Error,
+    Future = impl Future<Output = Result<Response<Body>, hyper::Error>> + Send,
+> + Clone
+where
+    F: Future<Output = Result<Response<Body>, hyper::Error>> + Send,
+{
+    service_fn(move |mut req: Request<Body>| {
+        let start = Instant::now();
+        let method = req.method().clone();
+        let path = req.uri().path().to_string();
+        let id = request_id(req.headers());
+        req.headers_mut().insert("x-request-id", id.clone());
+        let state = state.clone();
+        async move {
+            let mut resp = handler(req, state.clone(), remote).await?;
+            resp.headers_mut().insert("x-request-id", id.clone());
+            let elapsed = start.elapsed();
+            let id = id.to_str().unwrap();
+            state.log_access(id, &remote, &method, &path, resp.status(), elapsed);
+            state.warn_if_slow(id, &path, &resp, elapsed);
+            state.observe(&path, &resp, elapsed);
+            Ok::<_, hyper::Error>(resp)
+        }
+    })
+}
+
+type Incoming = Pin<Box<dyn Stream<Item = io::Result<Connection>> + Send>>;
+
+fn unix_incoming(config: &Config, path: &Path) -> io::Result<Incoming> {
+    let write_timeout = config.write_timeout_secs.map(Duration::from_secs);
+    debug!("Trying to bind server to socket: {}", path.display());
+    let listener = bind_unix(path)?;
+    info!("Used socket: {}", path.display());
+    if config.tls.is_some() {
+        warn!("TLS is only served on TCP listeners, ignoring tls for the unix socket");
+    }
+    let incoming = stream::unfold(listener, move |listener| async move {
+        let conn = listener
+            .accept()
+            .await
+            .and_then(|(stream, _)| Connection::unix(stream, write_timeout));
+        Some((conn, listener))
+    });
+    Ok(incoming.boxed())
+}
+
+/// One listener on `addr` for each of the configured workers.
+fn tcp_workers(
+    config: &Config,
+    tls: Option<TlsAcceptor>,
+    addr: SocketAddr,
+) -> io::Result<Vec<Incoming>> {
+    debug!("Trying to bind server to address: {}", addr);
+    let reuse_port = config.workers > 1;
+    let listener = bind(&addr, config.dual_stack, reuse_port)?;
+    // The others join the first's port, which may have been chosen for it.
+    let addr = listener.local_addr()?;
+    info!("Used address: {}", addr);
+    let mut workers = vec![tcp_incoming(config, tls.clone(), listener)];
+    for _ in 1..config.workers {
+        let listener = bind(&addr, config.dual_stack, true)?;
+        workers.push(tcp_incoming(config, tls.clone(), listener));
+    }
+    Ok(workers)
+}
+
+fn tcp_incoming(config: &Config, tls: Option<TlsAcceptor>, listener: TcpListener) -> Incoming {
+    let write_timeout = config.write_timeout_secs.map(Duration::from_secs);
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    let accepted = stream::unfold(listener, |listener| async {
+        let stream = listener.accept().await.map(|(stream, _)| stream);
+        Some((stream, listener))
+    });
+    match tls {
+        Some(acceptor) => accepted
+            .map(move |stream| {
+                let acceptor = acceptor.clone();
+                async move {
+                    let handshake = acceptor.accept(stream?);
+                    let stream = time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake)
+                        .await
+                        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))?;
+                    Connection::tls(stream, write_timeout)
+                }
+            })
+            .buffer_unordered(MAX_TLS_HANDSHAKES)
+            .boxed(),
+        None => accepted
+            .map(move |stream| stream.and_then(|stream| Connection::tcp(stream, write_timeout)))
+            .boxed(),
+    }
+}
+
+/// Skips connections that fail to be accepted, logging why.
+fn accepted(incoming: Incoming) -> impl accept::Accept<Conn = Connection, Error = io::Error> {
+    let incoming = incoming.filter_map(|conn| async move {
+        conn.map_err(|err| warn!("Cannot accept connection: {}", err))
+            .ok()
+    });
+    accept::from_stream(incoming.map(Ok::<_, io::Error>))
+}
+
+/// Serves `/admin` on `listener` until the runtime shuts down.
+async fn serve_admin(state: Arc<State>, listener: TcpListener) {
+    let incoming = tcp_incoming(&state.config(), state.tls.clone(), listener);
+    let server =
+        Server::builder(accepted(incoming)).serve(make_service_fn(move |conn: &Connection| {
+            future::ok::<_, Infallible>(logged(
+                state.clone(),
+                conn.remote_addr(),
+                router::admin_handler,
+            ))
+        }));
+    if let Err(err) = server.await {
+        warn!("Admin server error: {}", err);
+    }
+}
+
+/// Accepts connections on `unix_socket`, `addr`, or both, and serves them
+/// until SIGINT or SIGTERM. Each worker's listeners have an accept loop of
+/// their own, with the unix socket on the last.
+async fn serve(
+    state: Arc<State>,
+    addr: Option<SocketAddr>,
+    unix_socket: Option<&Path>,
+) -> Result<(), Error> {
+    let config = &state.config();
+    let mut workers = match addr {
+        Some(addr) => tcp_workers(config, state.tls.clone(), addr)
+            .map_err(|err| format_err!("cannot bind server address {}: {}", addr, err))?,
+        None => Vec::new(),
+    };
+    if let Some(path) = unix_socket {
+        if addr.is_none() && config.workers > 1 {
+            warn!("Serving the unix socket with one worker, workers only applies to TCP");
+        }
+        let unix = unix_incoming(config, path)
+            .map_err(|err| format_err!("cannot bind server socket {}: {}", path.display(), err))?;
+        match workers.pop() {
+            Some(tcp) => workers.push(stream::select(unix, tcp).boxed()),
+            None => workers.push(unix),
+        }
+    }
+    trace!("Creating service handler...");
+    let shutdown = shutdown_signal().shared();
+    let servers = workers.into_iter().enumerate().map(|(worker, incoming)| {
+        let state = state.clone();
+        let server =
+            Server::builder(accepted(incoming)).serve(make_service_fn(move |conn: &Connection| {
+                let worker = worker.to_string();
+                let metrics = &state.metrics;
+                metrics
+                    .worker_connections
+                    .with_label_values(&[&worker])
+                    .inc();
+                let requests = metrics.worker_requests.with_label_values(&[&worker]);
+                let mut service = service(state.clone(), conn.remote_addr());
+                future::ok::<_, Infallible>(service_fn(move |req| {
+                    requests.inc();
+                    service.call(req)
+                }))
+            }));
+        tokio::spawn(server.with_graceful_shutdown(shutdown.clone()))
+    });
+    let servers = servers.collect::<Vec<_>>();
+    state.ready.store(true, Ordering::SeqCst);
+    debug!("Run!");
+    for server in future::join_all(servers).await {
+        if let Ok(Err(err)) = server {
+            warn!("Server error: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/// Parses the command line and runs its subcommand.
+pub fn run() {
+    // Without RUST_LOG this service's lines all reach the logger so that
+    // `log_level` can raise the level once the config is loaded.
+    let mut logger = pretty_env_logger::formatted_builder();
+    match env::var("RUST_LOG") {
+        Ok(filters) => logger.parse_filters(&filters),
+        Err(_) => logger
+            .filter_level(LevelFilter::Error)
+            .filter_module("random_number_microservice", LevelFilter::Trace),
+    };
+    logger.init();
+    if env::var_os("RUST_LOG").is_none() {
+        log::set_max_level(LevelFilter::Error);
+    }
+
+    let matches = App::new("Server with keys")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("run the server")
+                .arg(
+                    Arg::with_name("address")
+                        .short("a")
+                        .long("address")
+                        .takes_value(true)
+                        .help("address of the server"),
+                )
+                .arg(
+                    Arg::with_name("unix-socket")
+                        .long("unix-socket")
+                        .takes_value(true)
+                        .help("unix socket to serve on, as well as TCP only with --address"),
+                )
+                .arg(
+                    Arg::with_name("workers")
+                        .long("workers")
+                        .takes_value(true)
+                        .help("accept loops sharing the TCP address through SO_REUSEPORT"),
+                )
+                .arg(
+                    Arg::with_name("tls-cert")
+                        .long("tls-cert")
+                        .takes_value(true)
+                        .requires("tls-key")
+                        .help("PEM certificate chain, serving HTTPS instead of HTTP"),
+                )
+                .arg(
+                    Arg::with_name("tls-key")
+                        .long("tls-key")
+                        .takes_value(true)
+                        .requires("tls-cert")
+                        .help("PEM private key for --tls-cert"),
+                )
+                .arg(
+                    Arg::with_name("tls-client-ca")
+                        .long("tls-client-ca")
+                        .takes_value(true)
+                        .requires("tls-cert")
+                        .help("PEM CA that client certificates must be signed by"),
+                )
+                .arg(
+                    Arg::with_name("self-test")
+                        .long("self-test")
+                        .help("draws from every distribution and exits without serving"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .short("c")
+                        .long("config")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("config file, later files override earlier ones"),
+                )
+                .arg(
+                    Arg::with_name("strict-config")
+                        .long("strict-config")
+                        .help("exits on an invalid config instead of using defaults"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("seeds the server generator, making responses reproducible"),
+                )
+                .subcommand(
+                    SubCommand::with_name("key")
+                        .about("generates a key for api_keys or signing_key_path and exits")
+                        .arg(
+                            Arg::with_name("encoding")
+                                .short("e")
+                                .long("encoding")
+                                .takes_value(true)
+                                .possible_values(&["hex", "base64", "base64url"])
+                                .default_value("base64url")
+                                .help("how the key's 32 random bytes are written"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .short("o")
+                                .long("output")
+                                .takes_value(true)
+                                .help("new file to write the key to, readable only by its owner"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("inspects config files")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("validates config files and RNG_ variables and exits")
+                        .arg(
+                            Arg::with_name("config")
+                                .multiple(true)
+                                .default_value("microservice.toml")
+                                .help("config files, later files override earlier ones"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("measures generation throughput without serving")
+                .arg(
+                    Arg::with_name("request").help(
+                        "distribution name or JSON request body, every distribution if omitted",
+                    ),
+                )
+                .arg(
+                    Arg::with_name("samples")
+                        .short("n")
+                        .long("samples")
+                        .takes_value(true)
+                        .default_value("1000000")
+                        .help("number of samples to generate for each row"),
+                )
+                .arg(
+                    Arg::with_name("duration")
+                        .short("d")
+                        .long("duration")
+                        .takes_value(true)
+                        .conflicts_with("samples")
+                        .help("seconds to run each row for instead of a number of samples"),
+                )
+                .arg(
+                    Arg::with_name("concurrency")
+                        .short("j")
+                        .long("concurrency")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("threads generating at once"),
+                )
+                .arg(
+                    Arg::with_name("batch")
+                        .short("b")
+                        .long("batch")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("samples generated by each call, as with ?count="),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .use_delimiter(true)
+                        .possible_values(&["json", "text", "csv", "cbor", "msgpack"])
+                        .default_value("json")
+                        .help("formats to serialize to, one row each"),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .short("c")
+                        .long("config")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("config file, later files override earlier ones"),
+                )
+                .arg(
+                    Arg::with_name("strict-config")
+                        .long("strict-config")
+                        .help("exits on an invalid config instead of using defaults"),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("seeds the server generator, making responses reproducible"),
+                ),
+        )
+        .get_matches();
+    if let Some(matches) = matches
+        .subcommand_matches("config")
+        .and_then(|matches| matches.subcommand_matches("check"))
+    {
+        let paths = matches.values_of("config").unwrap();
+        for path in paths.clone() {
+            if let Err(err) = read_config(path) {
+                eprintln!("cannot read config file {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+        match load_config(paths, true).and_then(|config| check_config(&config)) {
+            Ok(()) => println!("config ok"),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("bench") {
+        let config = config_from_args(matches);
+        let res = BenchPlan::from_args(matches)
+            .and_then(|plan| bench(config, matches.value_of("request"), &plan));
+        if let Err(err) = res {
+            eprintln!("bench failed: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    let matches = matches.subcommand_matches("run").unwrap();
+    if let Some(matches) = matches.subcommand_matches("key") {
+        let encoding = matches.value_of("encoding").unwrap().parse().unwrap();
+        if let Err(err) = generate_key(encoding, matches.value_of("output")) {
+            eprintln!("key failed: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+    if matches.is_present("self-test") {
+        process::exit(if self_test() { 0 } else { 1 });
+    }
+
+    info!("Rand Microservice - v0.1.0");
+    trace!("Starting...");
+    let config = config_from_args(matches);
+    // Only an explicit address adds a TCP listener to a unix socket.
+    let addr = match config.unix_socket {
+        Some(_) => config.address,
+        None => Some(
+            config
+                .address
+                .unwrap_or_else(|| SocketAddr::new(config.host, config.port)),
+        ),
+    };
+
+    let mut state = State::new(config).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+    let args = matches.clone();
+    state.loader = Some(Box::new(move || load_args_config(&args, true)));
+    let state = Arc::new(state);
+    let grpc = state.config().grpc_address.map(|addr| {
+        let listener = bind(&addr, state.config().dual_stack, false);
+        let listener = listener.unwrap_or_else(|err| {
+            eprintln!("cannot bind gRPC address {}: {}", addr, err);
+            process::exit(1);
+        });
+        info!("Used gRPC address: {}", listener.local_addr().unwrap());
+        listener
+    });
+    let admin = state.config().admin_address.map(|addr| {
+        let listener = bind(&addr, state.config().dual_stack, false);
+        let listener = listener.unwrap_or_else(|err| {
+            eprintln!("cannot bind admin address {}: {}", addr, err);
+            process::exit(1);
+        });
+        info!("Used admin address: {}", listener.local_addr().unwrap());
+        listener
+    });
+    let runtime = tokio::runtime::Runtime::new().expect("cannot start the runtime");
+    if let Some(listener) = grpc {
+        runtime.spawn(grpc::serve(state.clone(), listener));
+    }
+    if let Some(listener) = admin {
+        runtime.spawn(serve_admin(state.clone(), listener));
+    }
+    // A reload may change unix_socket, but the socket to remove is the one
+    // that was bound.
+    let unix_socket = state.config().unix_socket.clone();
+    let res = runtime.block_on(serve(state.clone(), addr, unix_socket.as_deref()));
+    if let Some(ref audit_log) = state.audit_log {
+        audit_log.close();
+    }
+    if let Err(err) = res {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    if let Some(ref path) = unix_socket {
+        if let Err(err) = fs::remove_file(path) {
+            warn!("Cannot remove socket {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv6Addr, TcpStream as StdTcpStream};
+
+    #[test]
+    fn metrics_count_responses_by_endpoint() {
+        let state = State::new(Config::default()).unwrap();
+        let resp = Response::builder().status(404).body(Body::empty()).unwrap();
+        state.observe("/nowhere", &resp, Duration::from_millis(3));
+        state.count_response(resp.status());
+        let metrics = String::from_utf8(state.metrics().unwrap()).unwrap();
+        for line in &[
+            "http_requests_total{code=\"404\",endpoint=\"other\"} 1",
+            "http_request_duration_seconds_bucket{endpoint=\"other\",le=\"0.0025\"} 0",
+            "http_request_duration_seconds_bucket{endpoint=\"other\",le=\"0.005\"} 1",
+            "http_responses_total{code=\"404\"} 1",
+            "rejections_total{reason=\"not_found\"} 0",
+            "worker_requests_total{worker=\"0\"} 0",
+            "requests_in_flight 0",
+        ] {
+            assert!(metrics.lines().any(|metric| metric == *line), "no {}", line);
+        }
+    }
+
+    #[test]
+    fn ipv6_addresses_parse() {
+        let config = toml::from_str::<Config>(r#"address = "[::]:8080""#).unwrap();
+        assert_eq!(
+            config.address,
+            Some(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 8080)))
+        );
+        assert!(config.dual_stack);
+        let config = toml::from_str::<Config>(r#"address = "[::1]:0""#).unwrap();
+        assert_eq!(
+            config.address,
+            Some(SocketAddr::from((Ipv6Addr::LOCALHOST, 0)))
+        );
+        assert!(toml::from_str::<Config>(r#"address = "::1:8080""#).is_err());
+    }
+
+    #[test]
+    fn dual_stack_listener_accepts_ipv4() {
+        let addr = "[::]:0".parse().unwrap();
+        let listener = match bind(&addr, true, false) {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("skipping, this host cannot bind IPv6: {}", err);
+                return;
+            }
+        };
+        let port = listener.local_addr().unwrap().port();
+        StdTcpStream::connect(("127.0.0.1", port)).unwrap();
+        listener.set_nonblocking(false).unwrap();
+        let (_, remote) = listener.accept().unwrap();
+        match remote {
+            SocketAddr::V6(remote) => {
+                assert_eq!(remote.ip().to_ipv4_mapped(), Some([127, 0, 0, 1].into()))
+            }
+            SocketAddr::V4(remote) => assert_eq!(*remote.ip(), IpAddr::from([127, 0, 0, 1])),
+        }
+    }
+}
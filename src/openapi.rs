@@ -0,0 +1,496 @@
+//! The OpenAPI 3 description served at `/openapi.json`. Distribution schemas
+//! come from `RngRequest::describe`, the same data `/distributions` serves,
+//! so they cannot drift from what validation enforces; routes are listed by
+//! hand next to `router::route`.
+
+use super::*;
+
+/// Method, path, summary and request body schema of every documented route.
+const OPERATIONS: &[(&str, &str, &str, Option<&str>)] = &[
+    (
+        "post",
+        "/random",
+        "Generate from a distribution",
+        Some("RngRequest"),
+    ),
+    (
+        "get",
+        "/random",
+        "Generate from a distribution given in the query",
+        None,
+    ),
+    (
+        "post",
+        "/random/typed",
+        "Generate from a distribution, tagging the value with its type",
+        Some("RngRequest"),
+    ),
+    (
+        "post",
+        "/random/{distribution}",
+        "Generate from the named distribution",
+        Some("Parameters"),
+    ),
+    (
+        "get",
+        "/random/stream",
+        "Stream values as newline-delimited JSON",
+        None,
+    ),
+    (
+        "post",
+        "/quantile",
+        "Evaluate a distribution's quantile function",
+        Some("RngRequest"),
+    ),
+    (
+        "post",
+        "/cdf",
+        "Evaluate a distribution's CDF",
+        Some("RngRequest"),
+    ),
+    (
+        "post",
+        "/validate",
+        "Check a request without generating",
+        Some("RngRequest"),
+    ),
+    (
+        "post",
+        "/verify",
+        "Check a sequence against its seed and checksum",
+        Some("VerifyRequest"),
+    ),
+    (
+        "post",
+        "/sample",
+        "Draw items without replacement",
+        Some("DrawRequest"),
+    ),
+    ("post", "/shuffle", "Shuffle items", Some("DrawRequest")),
+    (
+        "post",
+        "/quasirandom",
+        "Points of a Sobol or Halton sequence",
+        Some("QuasiRequest"),
+    ),
+    (
+        "post",
+        "/password",
+        "Passwords meeting a policy",
+        Some("PasswordPolicy"),
+    ),
+    ("get", "/string", "A random string over a charset", None),
+    (
+        "get",
+        "/bytes",
+        "Random bytes from the operating system",
+        None,
+    ),
+    ("get", "/uuid", "A version 4 or 7 UUID", None),
+    ("get", "/ulid", "A ULID", None),
+    ("get", "/roll", "Roll dice given in dice notation", None),
+    ("get", "/flip", "Flip a coin", None),
+    ("get", "/seed", "A seed from the operating system", None),
+    ("get", "/health", "Liveness", None),
+    ("get", "/healthz", "Liveness", None),
+    ("get", "/ready", "Readiness", None),
+    ("get", "/readyz", "Readiness", None),
+    ("get", "/stats", "Rejection counts", None),
+    ("get", "/version", "The server version", None),
+    (
+        "get",
+        "/distributions",
+        "The supported distributions and their parameters",
+        None,
+    ),
+    ("post", "/warmup", "Warm up every generator", None),
+    ("get", "/metrics", "Prometheus metrics", None),
+    ("get", "/streams", "The named streams", None),
+    ("delete", "/streams/{name}", "Reset a named stream", None),
+    ("get", "/openapi.json", "This document", None),
+    ("get", "/docs", "A Swagger UI page for this document", None),
+    ("get", "/admin/loglevel", "The log level", None),
+    (
+        "put",
+        "/admin/loglevel",
+        "Set the log level",
+        Some("LogLevel"),
+    ),
+    ("post", "/admin/reload", "Reload the config files", None),
+];
+
+/// Query parameters every generating endpoint accepts.
+const OPTIONS: &[(&str, &str, &str)] = &[
+    ("count", "integer", "Return a batch of this many values"),
+    (
+        "seed",
+        "integer",
+        "Generate from a generator seeded with this value",
+    ),
+    (
+        "index",
+        "integer",
+        "Skip this many draws of the seeded generator",
+    ),
+    (
+        "burnin",
+        "integer",
+        "Discard this many draws first, in seeded RNG modes",
+    ),
+    ("stream", "string", "Continue the named stream"),
+    (
+        "rng",
+        "string",
+        "The entropy source: thread_rng, os_rng, chacha or pcg",
+    ),
+    ("entropy_source", "string", "Another name for rng"),
+    ("format", "string", "json, text, csv, cbor or msgpack"),
+    ("envelope", "boolean", "Wrap a single value in an object"),
+    (
+        "stats",
+        "boolean",
+        "Summarize the batch instead of returning it",
+    ),
+    ("histogram", "boolean", "Return a histogram of the batch"),
+    (
+        "bins",
+        "integer",
+        "Histogram bins, at most max_bins (1000 by default)",
+    ),
+    ("scale", "number", "Multiply each value by this"),
+    ("offset", "number", "Add this to each value after scale"),
+    (
+        "jitter",
+        "number",
+        "Add uniform noise within plus or minus this",
+    ),
+    (
+        "assert_min",
+        "number",
+        "Fail the request if a value is below this",
+    ),
+    (
+        "assert_max",
+        "number",
+        "Fail the request if a value is above this",
+    ),
+    (
+        "antithetic",
+        "boolean",
+        "Return a value and its antithetic pair, drawn from u and 1 - u",
+    ),
+    (
+        "smooth",
+        "boolean",
+        "Add Gaussian noise to empirical draws, a smoothed bootstrap",
+    ),
+    (
+        "show_uniform",
+        "boolean",
+        "Include the uniform draw the value was transformed from",
+    ),
+    (
+        "with_density",
+        "boolean",
+        "Include the density at the value",
+    ),
+    ("timing", "boolean", "Include the generation time"),
+    ("fixed_point", "boolean", "Write numbers without exponents"),
+    ("pretty", "boolean", "Indent JSON output"),
+];
+
+/// The schema of a `json_type` name such as `array<number>`.
+fn type_schema(kind: &str) -> Value {
+    match kind
+        .strip_prefix("array<")
+        .and_then(|kind| kind.strip_suffix('>'))
+    {
+        Some(items) => serde_json::json!({"type": "array", "items": type_schema(items)}),
+        None if kind.is_empty() => serde_json::json!({}),
+        None => serde_json::json!({"type": kind}),
+    }
+}
+
+/// Adds a constraint to `schema`, descending into array items since ranges
+/// bound every element of an array parameter.
+fn constrain(schema: &mut Value, constraint: &Constraint) {
+    if schema["type"] == "array" {
+        if let Constraint::MinLength { length } = *constraint {
+            schema["minItems"] = length.into();
+            return;
+        }
+        if let Constraint::Range { .. } = *constraint {
+            return constrain(&mut schema["items"], constraint);
+        }
+    }
+    let note = match *constraint {
+        Constraint::Range {
+            min,
+            min_exclusive,
+            max,
+            max_exclusive,
+        } => {
+            if let Some(min) = min {
+                schema["minimum"] = min.into();
+                schema["exclusiveMinimum"] = min_exclusive.into();
+            }
+            if let Some(max) = max {
+                schema["maximum"] = max.into();
+                schema["exclusiveMaximum"] = max_exclusive.into();
+            }
+            return;
+        }
+        Constraint::MinLength { length } => format!("at least {} long", length),
+        Constraint::LessThan { field } => format!("less than {}", field),
+        Constraint::AtMost { field } => format!("at most {}", field),
+        Constraint::PositiveSum => "sums to a positive value".to_owned(),
+        Constraint::Custom { description } => description.to_owned(),
+    };
+    let description = match schema["description"].as_str() {
+        Some(description) => format!("{}; {}", description, note),
+        None => note,
+    };
+    schema["description"] = description.into();
+}
+
+fn distribution_schema(info: &DistributionInfo) -> Value {
+    let mut parameters = serde_json::json!({"type": "object", "properties": {}});
+    let mut required = Vec::new();
+    for parameter in &info.parameters {
+        // `components[].weight` is `weight` within each item of `components`.
+        let mut path = parameter.name.split("[].");
+        let mut parent = &mut parameters;
+        let mut name = path.next().unwrap();
+        for field in path {
+            parent = &mut parent["properties"][name]["items"];
+            if parent["properties"].is_null() {
+                parent["properties"] = serde_json::json!({});
+            }
+            name = field;
+        }
+        let mut schema = type_schema(&parameter.kind);
+        for constraint in &parameter.constraints {
+            constrain(&mut schema, constraint);
+        }
+        match parameter.default {
+            Some(ref default) => schema["default"] = default.clone(),
+            None if !parameter.name.contains("[].") => required.push(parameter.name.clone()),
+            None => {}
+        }
+        parent["properties"][name] = schema;
+    }
+    let mut fields = vec!["distribution"];
+    if !required.is_empty() {
+        parameters["required"] = required.into();
+        fields.push("parameters");
+    }
+    serde_json::json!({
+        "type": "object",
+        "required": fields,
+        "properties": {
+            "distribution": {"type": "string", "enum": [info.name]},
+            "parameters": parameters,
+        },
+        "x-value-type": info.value_type,
+    })
+}
+
+fn schemas() -> Value {
+    let codes = Rejection::ALL
+        .iter()
+        .map(|rejection| rejection.reason())
+        .collect::<Vec<_>>();
+    let distributions = RngRequest::defaults()
+        .iter()
+        .map(|request| distribution_schema(&request.describe()))
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "RngRequest": {
+            "oneOf": distributions,
+        },
+        "Parameters": {
+            "type": "object",
+            "description": "The parameters object of the distribution named in the path",
+        },
+        "VerifyRequest": {
+            "type": "object",
+            "required": ["seed", "count", "request", "checksum"],
+            "properties": {
+                "seed": {"type": "integer", "minimum": 0},
+                "count": {"type": "integer", "minimum": 1},
+                "request": {"$ref": "#/components/schemas/RngRequest"},
+                "checksum": {"type": "string"},
+            },
+        },
+        "DrawRequest": {
+            "type": "object",
+            "properties": {
+                "items": {"type": "array", "items": {}},
+                "n": {"type": "integer", "minimum": 0},
+                "k": {"type": "integer", "minimum": 0},
+            },
+        },
+        "QuasiRequest": {
+            "type": "object",
+            "required": ["sequence", "dimensions"],
+            "properties": {
+                "sequence": {"type": "string", "enum": ["sobol", "halton"]},
+                "dimensions": {"type": "integer", "minimum": 1},
+                "count": {"type": "integer", "minimum": 1, "default": 1},
+                "skip": {"type": "integer", "minimum": 0},
+                "stream": {"type": "string"},
+            },
+        },
+        "PasswordPolicy": {
+            "type": "object",
+            "properties": {
+                "length": {"type": "integer", "minimum": 1, "default": 16},
+                "upper": {"type": "boolean", "default": true},
+                "digits": {"type": "boolean", "default": true},
+                "symbols": {"type": "boolean", "default": true},
+                "require_upper": {"type": "boolean", "default": false},
+                "require_digits": {"type": "boolean", "default": false},
+                "require_symbols": {"type": "boolean", "default": false},
+            },
+        },
+        "LogLevel": {
+            "type": "object",
+            "required": ["level"],
+            "properties": {
+                "level": {
+                    "type": "string",
+                    "enum": ["off", "error", "warn", "info", "debug", "trace"],
+                },
+                "seconds": {"type": "integer", "minimum": 0},
+            },
+        },
+        "Error": {
+            "type": "object",
+            "required": ["error"],
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "required": ["code", "message"],
+                    "properties": {
+                        "code": {"type": "string", "enum": codes},
+                        "message": {"type": "string"},
+                        "details": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "field": {"type": "string"},
+                                    "problem": {"type": "string"},
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+        },
+    })
+}
+
+pub(crate) fn document() -> Value {
+    let mut paths = serde_json::Map::new();
+    for &(method, path, summary, body) in OPERATIONS {
+        let mut operation = serde_json::json!({
+            "summary": summary,
+            "responses": {
+                "200": {"description": "Success"},
+                "default": {
+                    "description": "Error",
+                    "content": {
+                        "application/json": {
+                            "schema": {"$ref": "#/components/schemas/Error"},
+                        },
+                    },
+                },
+            },
+        });
+        let mut parameters = Vec::new();
+        for name in ["distribution", "name"] {
+            if path.contains(&format!("{{{}}}", name)) {
+                parameters.push(serde_json::json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": {"type": "string"},
+                }));
+            }
+        }
+        if path.starts_with("/random") {
+            parameters.extend(OPTIONS.iter().map(|&(name, kind, description)| {
+                serde_json::json!({
+                    "name": name,
+                    "in": "query",
+                    "description": description,
+                    "schema": {"type": kind},
+                })
+            }));
+        }
+        if !parameters.is_empty() {
+            operation["parameters"] = parameters.into();
+        }
+        if let Some(schema) = body {
+            operation["requestBody"] = serde_json::json!({
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": {"$ref": format!("#/components/schemas/{}", schema)},
+                    },
+                },
+            });
+        }
+        let item = paths
+            .entry(path.to_owned())
+            .or_insert_with(|| serde_json::json!({}));
+        item[method] = operation;
+    }
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {"schemas": schemas()},
+    })
+}
+
+/// A Swagger UI page for `/docs`, loading the UI itself from a CDN.
+pub(crate) const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>API documentation</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+<script>SwaggerUIBundle({url: "openapi.json", dom_id: "#swagger-ui"});</script>
+</body>
+</html>
+"##;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_route_is_documented() {
+        let document = document();
+        let templates = ["/random/{distribution}", "/streams/{name}"];
+        // The WebSocket upgrade has no OpenAPI description.
+        let routes = router::ROUTES.iter().filter(|&&route| route != "/ws");
+        for route in routes.chain(&templates) {
+            assert!(
+                document["paths"].get(route).is_some(),
+                "{} is missing",
+                route
+            );
+        }
+    }
+}
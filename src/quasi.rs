@@ -0,0 +1,173 @@
+//! Low-discrepancy sequences. Both are indexed from 0, whose point is the
+//! origin, so any index can be computed directly without the ones before it.
+
+/// Primitive polynomial degree, coefficients and initial direction numbers
+/// `m_1..m_s` for Sobol dimensions 2 onwards, from Joe and Kuo's
+/// new-joe-kuo-6.21201 table.
+const SOBOL_DIRECTIONS: [(u32, u32, &[u32]); 20] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+    (6, 19, &[1, 1, 1, 15, 7, 5]),
+    (6, 22, &[1, 3, 1, 15, 13, 25]),
+    (6, 25, &[1, 1, 5, 5, 19, 61]),
+    (7, 1, &[1, 3, 7, 11, 23, 15, 103]),
+    (7, 4, &[1, 3, 7, 13, 13, 15, 69]),
+];
+
+pub const MAX_SOBOL_DIMENSIONS: usize = SOBOL_DIRECTIONS.len() + 1;
+
+/// Points per dimension before a Sobol sequence repeats.
+pub const SOBOL_PERIOD: u64 = 1 << 32;
+
+/// The 32 direction numbers of each of the first `dimensions` Sobol
+/// dimensions, scaled to the top bits of a `u32`.
+pub fn sobol_directions(dimensions: usize) -> Vec<[u32; 32]> {
+    let mut directions = vec![[0; 32]; dimensions];
+    for (bit, v) in directions[0].iter_mut().enumerate() {
+        *v = 1 << (31 - bit);
+    }
+    for (v, &(degree, coefficients, initial)) in
+        directions.iter_mut().skip(1).zip(SOBOL_DIRECTIONS.iter())
+    {
+        let s = degree as usize;
+        for (bit, &m) in initial.iter().enumerate() {
+            v[bit] = m << (31 - bit);
+        }
+        for bit in s..32 {
+            v[bit] = v[bit - s] ^ (v[bit - s] >> s);
+            for k in 1..s {
+                if (coefficients >> (s - 1 - k)) & 1 == 1 {
+                    v[bit] ^= v[bit - k];
+                }
+            }
+        }
+    }
+    directions
+}
+
+/// The Sobol point at `index`, which must be below `SOBOL_PERIOD`, in Gray
+/// code order.
+pub fn sobol(directions: &[[u32; 32]], index: u64) -> Vec<f64> {
+    let gray = index ^ (index >> 1);
+    directions
+        .iter()
+        .map(|v| {
+            let x = (0..32)
+                .filter(|bit| (gray >> bit) & 1 == 1)
+                .fold(0, |x, bit| x ^ v[bit]);
+            f64::from(x) / SOBOL_PERIOD as f64
+        })
+        .collect()
+}
+
+/// The first `count` primes, one base per Halton dimension.
+pub fn primes(count: usize) -> Vec<u64> {
+    let mut primes = Vec::with_capacity(count);
+    let mut candidate = 2;
+    while primes.len() < count {
+        if primes
+            .iter()
+            .take_while(|&&p| p * p <= candidate)
+            .all(|&p| candidate % p != 0)
+        {
+            primes.push(candidate);
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+/// The Halton point at `index`: its radical inverse in each prime base.
+pub fn halton(bases: &[u64], index: u64) -> Vec<f64> {
+    bases
+        .iter()
+        .map(|&base| {
+            let (mut n, mut x, mut scale) = (index, 0.0, 1.0);
+            while n > 0 {
+                scale /= base as f64;
+                x += (n % base) as f64 * scale;
+                n /= base;
+            }
+            x
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sobol_points_match_joe_kuo() {
+        let expected = [
+            [0.0, 0.0, 0.0],
+            [0.5, 0.5, 0.5],
+            [0.75, 0.25, 0.25],
+            [0.25, 0.75, 0.75],
+            [0.375, 0.375, 0.625],
+            [0.875, 0.875, 0.125],
+            [0.625, 0.125, 0.875],
+            [0.125, 0.625, 0.375],
+        ];
+        let directions = sobol_directions(3);
+        for (index, point) in expected.iter().enumerate() {
+            assert_eq!(sobol(&directions, index as u64), point, "index {}", index);
+        }
+    }
+
+    #[test]
+    fn sobol_dimensions_stratify_the_unit_interval() {
+        // The first 2^k points of every dimension put one point in each
+        // interval of width 2^-k, which needs each direction number `m_i`
+        // to be odd and below 2^i.
+        let directions = sobol_directions(MAX_SOBOL_DIMENSIONS);
+        let points = (0..1 << 10)
+            .map(|index| sobol(&directions, index))
+            .collect::<Vec<_>>();
+        for dimension in 0..MAX_SOBOL_DIMENSIONS {
+            let mut cells = points
+                .iter()
+                .map(|point| (point[dimension] * points.len() as f64) as usize)
+                .collect::<Vec<_>>();
+            cells.sort_unstable();
+            assert!(
+                cells.iter().copied().eq(0..points.len()),
+                "dimension {}",
+                dimension + 1
+            );
+        }
+    }
+
+    #[test]
+    fn halton_points_are_radical_inverses() {
+        assert_eq!(primes(5), [2, 3, 5, 7, 11]);
+        let expected = [
+            [0.0, 0.0],
+            [1.0 / 2.0, 1.0 / 3.0],
+            [1.0 / 4.0, 2.0 / 3.0],
+            [3.0 / 4.0, 1.0 / 9.0],
+            [1.0 / 8.0, 4.0 / 9.0],
+            [5.0 / 8.0, 7.0 / 9.0],
+            [3.0 / 8.0, 2.0 / 9.0],
+        ];
+        for (index, point) in expected.iter().enumerate() {
+            let x = halton(&primes(2), index as u64);
+            for (x, expected) in x.iter().zip(point) {
+                assert!((x - expected).abs() < 1e-15, "index {}: {:?}", index, x);
+            }
+        }
+    }
+}
@@ -0,0 +1,1340 @@
+//! Maps requests onto handlers. Each endpoint is an `async fn` taking the
+//! request's `Context`; new endpoints only need a handler and a line in
+//! `route`.
+
+use super::*;
+use tokio::runtime::Handle;
+use tokio::task;
+
+/// What a handler knows about the request it is serving.
+struct Context {
+    req: Request<Body>,
+    state: Arc<State>,
+    remote: SocketAddr,
+    query: Value,
+    /// The format errors are reported in until a handler has parsed options
+    /// of its own.
+    error_format: Format,
+}
+
+impl Context {
+    fn reject(&self, rejection: Rejection, message: String) -> Response<Body> {
+        self.state
+            .reject(&self.remote, rejection, message, self.error_format)
+    }
+
+    fn reject_error(&self, err: &Error) -> Response<Body> {
+        self.state
+            .reject_error(&self.remote, err, self.error_format)
+    }
+
+    fn options(&self, typed: bool) -> Result<RandomOptions, Error> {
+        RandomOptions::parse(&self.query, self.req.headers(), &self.state.config(), typed)
+    }
+
+    /// Reads the request body up to the limit configured for its path,
+    /// answering 413 instead when the body is larger and 415 when it is not
+    /// JSON.
+    async fn body(&mut self, format: Format) -> Result<Vec<u8>, Response<Body>> {
+        let limit = body_limit(&self.state.config(), self.req.uri().path());
+        let too_large = |ctx: &Self| {
+            ctx.state.reject(
+                &ctx.remote,
+                Rejection::PayloadTooLarge,
+                format!("request body exceeds the limit of {} bytes", limit),
+                format,
+            )
+        };
+        let length = self
+            .req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<usize>().ok());
+        if length.is_some_and(|length| length > limit) {
+            return Err(too_large(self));
+        }
+        request_content_type(self.req.headers()).map_err(|err| {
+            self.state
+                .reject(&self.remote, Rejection::UnsupportedMediaType, err, format)
+        })?;
+        let gzip = request_encoding(self.req.headers()).map_err(|err| {
+            self.state
+                .reject(&self.remote, Rejection::UnsupportedMediaType, err, format)
+        })?;
+        let mut body = mem::take(self.req.body_mut());
+        let mut buffer = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|err| {
+                self.state.reject(
+                    &self.remote,
+                    Rejection::BadRequest,
+                    format!("cannot read request body: {}", err),
+                    format,
+                )
+            })?;
+            if buffer.len() + chunk.len() > limit {
+                return Err(too_large(self));
+            }
+            buffer.extend_from_slice(&chunk);
+        }
+        if !gzip {
+            return Ok(buffer);
+        }
+        match gunzip(&buffer, limit) {
+            Ok(Some(body)) => Ok(body),
+            Ok(None) => Err(too_large(self)),
+            Err(err) => Err(self.state.reject(
+                &self.remote,
+                Rejection::BadRequest,
+                format!("invalid gzip request body: {}", err),
+                format,
+            )),
+        }
+    }
+}
+
+/// Decrements the in-flight count when a request's response is ready or the
+/// request is abandoned.
+struct InFlight(Arc<State>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub(crate) async fn microservice_handler(
+    req: Request<Body>,
+    state: Arc<State>,
+    remote: SocketAddr,
+) -> Result<Response<Body>, hyper::Error> {
+    let encoding = Encoding::negotiate(req.headers());
+    let settings = state.settings();
+    let config = &settings.config;
+    let cors = config.cors.as_ref();
+    let origin = cors.and_then(|cors| cors.origin(req.headers()).cloned());
+    let depth = state.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+    let in_flight = InFlight(state.clone());
+    let mut resp = match cors {
+        Some(cors) if cors.is_preflight(&req) => cors.preflight(),
+        _ => dispatch(req, &state, remote).await,
+    };
+    drop(in_flight);
+    let headers = resp.headers_mut();
+    if let (Some(cors), Some(origin)) = (cors, origin) {
+        cors.allow(origin, headers);
+    }
+    headers.insert("x-queue-depth", HeaderValue::from(depth));
+    if config.queue_high_water.is_some_and(|mark| depth > mark) {
+        headers.entry(RETRY_AFTER).or_insert(HeaderValue::from(1));
+    }
+    for (name, value) in &settings.response_headers {
+        headers.insert(name, value.clone());
+    }
+    state.count_response(resp.status());
+    compress(resp, encoding, config.compression_min_bytes).await
+}
+
+impl Context {
+    fn new(req: Request<Body>, state: Arc<State>, remote: SocketAddr) -> Self {
+        let query = {
+            let uri = req.uri().query().unwrap_or("");
+            queryst::parse(uri).unwrap_or(Value::Null)
+        };
+        let error_format = Format::negotiate(&query, req.headers()).unwrap_or(Format::Json);
+        Context {
+            req,
+            state,
+            remote,
+            query,
+            error_format,
+        }
+    }
+
+    /// The 401 or 403 answering a request without a configured API key.
+    fn unauthenticated(&self) -> Option<Response<Body>> {
+        let (rejection, message) = self.state.authenticate(self.req.headers()).err()?;
+        let mut resp = self.reject(rejection, message.into());
+        if let Rejection::Unauthorized = rejection {
+            resp.headers_mut()
+                .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        Some(resp)
+    }
+}
+
+async fn dispatch(req: Request<Body>, state: &Arc<State>, remote: SocketAddr) -> Response<Body> {
+    let ctx = Context::new(req, state.clone(), remote);
+    let exempt = matches!(
+        ctx.req.uri().path(),
+        "/health" | "/healthz" | "/ready" | "/readyz" | "/metrics"
+    );
+    if let (false, Err(limited)) = (exempt, state.check_rate_limit(&remote, ctx.req.headers())) {
+        return ctx.reject_error(&limited.into());
+    }
+    if let (false, Some(resp)) = (exempt, ctx.unauthenticated()) {
+        return resp;
+    }
+    if let (false, Err(overloaded)) = (exempt, state.check_load()) {
+        return ctx.reject_error(&overloaded.into());
+    }
+    let timeout = match state.config().request_timeout_ms {
+        Some(timeout) => timeout,
+        None => return delayed(ctx).await,
+    };
+    let error_format = ctx.error_format;
+    // Generation never yields, so the handler runs on a blocking thread for
+    // the timeout to fire while it is generating. A handler that runs over
+    // finishes there and its response is dropped.
+    let handler = task::spawn_blocking(move || Handle::current().block_on(delayed(ctx)));
+    match time::timeout(Duration::from_millis(timeout), handler).await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(err)) => panic::resume_unwind(err.into_panic()),
+        Err(_) => state.reject(
+            &remote,
+            Rejection::RequestTimeout,
+            format!("request took longer than {} ms", timeout),
+            error_format,
+        ),
+    }
+}
+
+/// Routes `ctx`, first sleeping for its `delay_ms` when chaos is enabled.
+async fn delayed(ctx: Context) -> Response<Body> {
+    let state = ctx.state.clone();
+    if !state.config().chaos_enabled {
+        return route(ctx).await;
+    }
+    match query_param::<u64>(&ctx.query, "delay_ms") {
+        Ok(Some(delay)) => {
+            let delay = Duration::from_millis(delay.min(state.config().max_delay_ms));
+            let resp = route(ctx).await;
+            time::sleep(delay).await;
+            resp
+        }
+        Ok(None) => route(ctx).await,
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+async fn route(ctx: Context) -> Response<Body> {
+    let method = ctx.req.method().clone();
+    let path = ctx.req.uri().path().to_owned();
+    match (&method, path.as_str()) {
+        (&Method::POST, "/random") => random(ctx, None).await,
+        (&Method::POST, path) if path.starts_with("/random/") => {
+            random(ctx, path.strip_prefix("/random/")).await
+        }
+        (&Method::GET, "/random") => random_query(ctx),
+        (&Method::GET, "/random/stream") => random_stream(ctx),
+        (&Method::GET, "/ws") => websocket(ctx),
+        (&Method::POST, "/quantile") => evaluate(ctx, "quantile", "p", RngRequest::quantile).await,
+        (&Method::POST, "/cdf") => evaluate(ctx, "cdf", "x", RngRequest::cdf).await,
+        (&Method::POST, "/validate") => validate(ctx).await,
+        (&Method::POST, "/verify") => verify_sequence(ctx).await,
+        (&Method::POST, "/sample") => draw(ctx, "sample").await,
+        (&Method::POST, "/shuffle") => draw(ctx, "shuffle").await,
+        (&Method::POST, "/quasirandom") => quasi_random(ctx).await,
+        (&Method::POST, "/password") => password(ctx).await,
+        (&Method::GET, "/string") => string(ctx),
+        (&Method::GET, "/bytes") => bytes(ctx),
+        (&Method::GET, "/uuid") => identifiers(ctx, "uuid"),
+        (&Method::GET, "/ulid") => identifiers(ctx, "ulid"),
+        (&Method::GET, "/roll") => roll(ctx),
+        (&Method::GET, "/flip") => flip(ctx),
+        (&Method::GET, "/seed") => seed(ctx),
+        (&Method::GET, "/health" | "/healthz") => health(),
+        (&Method::GET, "/ready" | "/readyz") => ready(ctx),
+        (&Method::GET, "/stats") => stats(ctx),
+        (&Method::GET, "/version") => version(ctx),
+        (&Method::GET, "/distributions") => distributions(ctx),
+        (&Method::GET, "/openapi.json") => openapi(ctx),
+        (&Method::GET, "/docs") => docs(),
+        (&Method::POST, "/warmup") => warmup(ctx),
+        (&Method::GET, "/metrics") => metrics(ctx),
+        (&Method::GET, "/streams") => streams(ctx),
+        (&Method::DELETE, path) if path.starts_with("/streams/") => {
+            reset_stream(ctx, &path["/streams/".len()..])
+        }
+        // Without a listener of their own, the admin routes are only served
+        // to clients holding an API key.
+        (_, path) if path.starts_with("/admin/") => {
+            let config = ctx.state.config();
+            match (config.admin_address, config.api_keys.is_empty()) {
+                (None, false) => admin(ctx).await,
+                _ => ctx.reject(Rejection::NotFound, "Not Found".into()),
+            }
+        }
+        (method, path) if endpoint(path) != "other" => ctx.reject(
+            Rejection::MethodNotAllowed,
+            format!("{} is not allowed on {}", method, path),
+        ),
+        _ => ctx.reject(Rejection::NotFound, "Not Found".into()),
+    }
+}
+
+/// Serves a connection to `admin_address`, which has only the `/admin`
+/// routes.
+pub(crate) async fn admin_handler(
+    req: Request<Body>,
+    state: Arc<State>,
+    remote: SocketAddr,
+) -> Result<Response<Body>, hyper::Error> {
+    let ctx = Context::new(req, state, remote);
+    Ok(match ctx.unauthenticated() {
+        Some(resp) => resp,
+        None => admin(ctx).await,
+    })
+}
+
+async fn admin(ctx: Context) -> Response<Body> {
+    let method = ctx.req.method().clone();
+    let path = ctx.req.uri().path().to_owned();
+    match (&method, path.as_str()) {
+        (&Method::GET | &Method::PUT, "/admin/loglevel") => log_level(ctx).await,
+        (&Method::POST, "/admin/reload") => reload(ctx),
+        (method, "/admin/loglevel" | "/admin/reload") => ctx.reject(
+            Rejection::MethodNotAllowed,
+            format!("{} is not allowed on {}", method, path),
+        ),
+        _ => ctx.reject(Rejection::NotFound, "Not Found".into()),
+    }
+}
+
+/// `GET` reports the level of this service's log lines and `PUT` sets it,
+/// putting the previous level back after `seconds` when given. Lines that
+/// `RUST_LOG` filters out stay filtered out.
+async fn log_level(mut ctx: Context) -> Response<Body> {
+    if ctx.req.method() == Method::PUT {
+        let body = match ctx.body(Format::Json).await {
+            Ok(body) => body,
+            Err(resp) => return resp,
+        };
+        let res = serde_json::from_slice::<LogLevel>(&body)
+            .map_err(Error::from)
+            .and_then(|change| {
+                let level = change
+                    .level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| format_err!("invalid level {:?}", change.level))?;
+                Ok((level, change.seconds))
+            });
+        let (level, seconds) = match res {
+            Ok(change) => change,
+            Err(err) => return ctx.reject(Rejection::Unprocessable, err.to_string()),
+        };
+        let previous = log::max_level();
+        let changes = &ctx.state.log_level_changes;
+        let change = changes.fetch_add(1, Ordering::SeqCst) + 1;
+        log::set_max_level(level);
+        info!("Log level set to {}", level);
+        if let Some(seconds) = seconds {
+            let state = ctx.state.clone();
+            tokio::spawn(async move {
+                time::sleep(Duration::from_secs(seconds)).await;
+                // A later change replaces this one, revert and all.
+                if state.log_level_changes.load(Ordering::SeqCst) == change {
+                    log::set_max_level(previous);
+                    info!("Log level set back to {}", previous);
+                }
+            });
+        }
+    }
+    let body = serde_json::to_vec(&LogLevel {
+        level: log::max_level().to_string().to_lowercase(),
+        seconds: None,
+    })
+    .unwrap();
+    Response::builder()
+        .header(CONTENT_TYPE, Format::Json.content_type())
+        .body(body.into())
+        .unwrap()
+}
+
+fn reload(ctx: Context) -> Response<Body> {
+    match ctx.state.reload() {
+        Ok(()) => {
+            let body = serde_json::to_vec(&ReloadResponse { reloaded: true }).unwrap();
+            Response::builder()
+                .header(CONTENT_TYPE, Format::Json.content_type())
+                .body(body.into())
+                .unwrap()
+        }
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+/// Every fixed path `route` serves.
+pub(crate) const ROUTES: &[&str] = &[
+    "/random",
+    "/random/stream",
+    "/random/typed",
+    "/ws",
+    "/quantile",
+    "/cdf",
+    "/validate",
+    "/verify",
+    "/sample",
+    "/shuffle",
+    "/quasirandom",
+    "/password",
+    "/string",
+    "/bytes",
+    "/uuid",
+    "/ulid",
+    "/roll",
+    "/flip",
+    "/seed",
+    "/health",
+    "/healthz",
+    "/ready",
+    "/readyz",
+    "/stats",
+    "/version",
+    "/distributions",
+    "/openapi.json",
+    "/docs",
+    "/warmup",
+    "/metrics",
+    "/streams",
+    "/admin/loglevel",
+    "/admin/reload",
+];
+
+/// The label `/metrics` counts a request under: its route, with path
+/// parameters collapsed so clients cannot create new series.
+pub(crate) fn endpoint(path: &str) -> &'static str {
+    match ROUTES.iter().find(|&&route| route == path) {
+        Some(route) => route,
+        None if path.starts_with("/random/") => "/random/{distribution}",
+        None if path.starts_with("/streams/") => "/streams/{name}",
+        None => "other",
+    }
+}
+
+/// `POST /random`, `/random/typed` and `/random/{distribution}`, where
+/// `suffix` is what follows `/random/`.
+async fn random(mut ctx: Context, suffix: Option<&str>) -> Response<Body> {
+    let (typed, distribution) = match suffix {
+        None => (false, None),
+        Some("typed") => (true, None),
+        Some(name) => {
+            let known = RngRequest::defaults()
+                .iter()
+                .map(RngRequest::name)
+                .find(|&known| known == name);
+            match known {
+                Some(name) => (false, Some(name)),
+                None => {
+                    return ctx.reject(
+                        Rejection::NotFound,
+                        format!("unknown distribution {:?}", name),
+                    )
+                }
+            }
+        }
+    };
+    let mut options = match ctx.options(typed) {
+        Ok(options) => options,
+        Err(err) => return ctx.reject(Rejection::Unprocessable, err.to_string()),
+    };
+    let ndjson = ctx
+        .req
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == NDJSON);
+    if ndjson && request_encoding(ctx.req.headers()) != Ok(false) {
+        return ctx.reject(
+            Rejection::UnsupportedMediaType,
+            "NDJSON request bodies must not be compressed".into(),
+        );
+    }
+    if ndjson {
+        return generate_lines(ctx, options, distribution);
+    }
+    let body = match ctx.body(options.format).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    let config = &ctx.state.config();
+    let res = match distribution {
+        Some(name) => parse_parameters(&body, name, config),
+        None => serde_json::from_slice(&body)
+            .map_err(Error::from)
+            .and_then(|mut request| {
+                if let Some(query) = body_options(&mut request, &ctx.query)? {
+                    options = RandomOptions::parse(&query, ctx.req.headers(), config, typed)?;
+                }
+                deserialize_request(request, config)
+            }),
+    };
+    match res {
+        Ok(request) => random_response(&request, &options, &ctx.state, &ctx.remote),
+        Err(err) => ctx.state.reject_error(&ctx.remote, &err, options.format),
+    }
+}
+
+/// `GET /random`, with the request spelled out in the query string.
+fn random_query(ctx: Context) -> Response<Body> {
+    let config = &ctx.state.config();
+    let res = query_request(&ctx.query, config).and_then(|(request, query)| {
+        let options = RandomOptions::parse(&query, ctx.req.headers(), config, false)?;
+        Ok((request, options))
+    });
+    match res {
+        Ok((request, options)) => random_response(&request, &options, &ctx.state, &ctx.remote),
+        Err(err) => ctx.reject_error(&err),
+    }
+}
+
+/// `GET /random/stream`: the request of `GET /random`, drawn every
+/// `interval_ms` until `limit` values are sent or the client goes away. Values
+/// are sent one per line, or as server-sent events when the client accepts
+/// `text/event-stream`.
+fn random_stream(ctx: Context) -> Response<Body> {
+    let config = &ctx.state.config();
+    let events = ctx
+        .req
+        .headers()
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|accept| accept.to_str().ok())
+        .any(|accept| accept.contains("text/event-stream"));
+    let res = query_request(&ctx.query, config).and_then(|(request, query)| {
+        let options = RandomOptions::parse(&query, ctx.req.headers(), config, false)?;
+        if events && options.format.binary() {
+            bail!("server-sent events cannot carry binary formats");
+        }
+        let interval = query_param(&ctx.query, "interval_ms")?.unwrap_or(1000);
+        let limit = query_param::<u64>(&ctx.query, "limit")?;
+        Ok((request, options, Duration::from_millis(interval), limit))
+    });
+    let (request, options, interval, limit) = match res {
+        Ok(stream) => stream,
+        Err(err) => return ctx.reject_error(&err),
+    };
+    let content_type = match options.format {
+        _ if events => "text/event-stream",
+        Format::Json => NDJSON,
+        format => format.content_type(),
+    };
+    let format = options.format;
+    let generator = Arc::new((request, options, ctx.state.clone()));
+    let values = stream::unfold(Some(0), move |sent| {
+        let generator = generator.clone();
+        async move {
+            let (request, options, state) = &*generator;
+            let sent = sent.filter(|&sent| limit.is_none_or(|limit| sent < limit))?;
+            if sent > 0 {
+                time::sleep(interval).await;
+            }
+            // Each event of a seeded stream draws from the next seed, so the
+            // stream replays without repeating one value.
+            let mut options = options.clone();
+            options.seed = options.seed.map(|seed| seed.wrapping_add(sent));
+            // A failed draw would fail again, so it ends the stream.
+            let (body, next) = match generate(request, &options, state) {
+                Ok(body) => (body, Some(sent + 1)),
+                Err(err) => (serialize(format, &ErrorResponse::new(&err)).unwrap(), None),
+            };
+            Some((Ok::<_, Infallible>(body), next))
+        }
+    })
+    .map(move |body| {
+        body.map(|mut body| {
+            if events {
+                let text = String::from_utf8_lossy(&body);
+                let data = text.lines().map(|line| format!("data: {}\n", line));
+                body = data.collect::<String>().into_bytes();
+            }
+            if !format.binary() {
+                body.push(b'\n');
+            }
+            body
+        })
+    });
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .header(CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(values))
+        .unwrap()
+}
+
+/// Generates `request` for `/random`, tagging the response with the seed that
+/// replays it and what was generated.
+fn random_response(
+    request: &RngRequest,
+    options: &RandomOptions,
+    state: &State,
+    remote: &SocketAddr,
+) -> Response<Body> {
+    let res = state
+        .check_distribution_limit(remote, request)
+        .map_err(Error::from)
+        .and_then(|()| generate(request, options, state));
+    let body = match res {
+        Ok(body) => body,
+        Err(err) => return state.reject_error(remote, &err, options.format),
+    };
+    let signature = state.sign(&body);
+    let mut resp = respond(options.format, body);
+    if let Some(signature) = signature {
+        resp.headers_mut()
+            .insert("x-signature", HeaderValue::from_str(&signature).unwrap());
+    }
+    if let Some(seed) = options.replay_seed(state) {
+        resp.headers_mut().insert("x-seed", HeaderValue::from(seed));
+    }
+    resp.extensions_mut().insert(Generated {
+        distribution: request.name(),
+        count: options.count.unwrap_or(1),
+    });
+    resp
+}
+
+/// `GET /ws`, a WebSocket on which each message is a `POST /random` body and
+/// gets back what that request would, with the upgrade request's query as
+/// the defaults for every message's options.
+fn websocket(mut ctx: Context) -> Response<Body> {
+    if !ws::is_upgrade(ctx.req.headers()) {
+        return ctx.reject(
+            Rejection::BadRequest,
+            "expected a WebSocket version 13 upgrade".into(),
+        );
+    }
+    let accept = match ctx.req.headers().get("sec-websocket-key") {
+        Some(key) => ws::accept_key(key.to_str().unwrap_or("")),
+        None => return ctx.reject(Rejection::BadRequest, "missing Sec-WebSocket-Key".into()),
+    };
+    if let Err(err) = ctx.options(false) {
+        return ctx.reject(Rejection::Unprocessable, err.to_string());
+    }
+    let upgrade = hyper::upgrade::on(&mut ctx.req);
+    // Each message gets a request ID of its own in the audit log.
+    let mut headers = ctx.req.headers().clone();
+    headers.remove("x-request-id");
+    let (state, remote, query) = (ctx.state.clone(), ctx.remote, ctx.query.clone());
+    tokio::spawn(async move {
+        let upgraded = match upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(err) => return warn!("WebSocket upgrade failed: {}", err),
+        };
+        let limit = body_limit(&state.config(), "/ws");
+        let res = ws::session(upgraded, limit, |message| {
+            websocket_reply(message, &query, &headers, &state, &remote)
+        })
+        .await;
+        if let Err(err) = res {
+            debug!("WebSocket from {} ended: {}", remote, err);
+        }
+    });
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("upgrade", "websocket")
+        .header("connection", "upgrade")
+        .header("sec-websocket-accept", accept)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn websocket_reply(
+    message: &[u8],
+    query: &Value,
+    headers: &HeaderMap,
+    state: &State,
+    remote: &SocketAddr,
+) -> ws::Reply {
+    let mut format = Format::Json;
+    let res = serde_json::from_slice(message)
+        .map_err(Error::from)
+        .and_then(|mut request| {
+            state.check_rate_limit(remote, headers)?;
+            let overrides = body_options(&mut request, query)?;
+            let query = overrides.as_ref().unwrap_or(query);
+            let options = RandomOptions::parse(query, headers, &state.config(), false)?;
+            format = options.format;
+            let request = deserialize_request(request, &state.config())?;
+            state.check_distribution_limit(remote, &request)?;
+            generate(&request, &options, state)
+        });
+    let body =
+        res.unwrap_or_else(|err| serialize(format, &ErrorResponse::new(&err)).unwrap_or_default());
+    (format.binary(), body)
+}
+
+enum Line {
+    Complete(Vec<u8>),
+    TooLong,
+}
+
+/// Splits a request body into lines as it arrives, holding at most `limit`
+/// bytes of an unfinished line.
+struct Lines {
+    body: Body,
+    buffer: Vec<u8>,
+    limit: usize,
+    skipping: bool,
+    done: bool,
+}
+
+impl Lines {
+    async fn next(&mut self) -> Option<Result<Line, hyper::Error>> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|&byte| byte == b'\n') {
+                let rest = self.buffer.split_off(end + 1);
+                let mut line = mem::replace(&mut self.buffer, rest);
+                line.pop();
+                if mem::replace(&mut self.skipping, false) {
+                    continue;
+                }
+                if line.len() > self.limit {
+                    return Some(Ok(Line::TooLong));
+                }
+                return Some(Ok(Line::Complete(line)));
+            }
+            if self.skipping {
+                self.buffer.clear();
+            } else if self.buffer.len() > self.limit {
+                self.buffer.clear();
+                self.skipping = true;
+                return Some(Ok(Line::TooLong));
+            }
+            if self.done {
+                return None;
+            }
+            match self.body.data().await {
+                Some(Ok(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Some(Err(err)) => return Some(Err(err)),
+                None => {
+                    self.done = true;
+                    if !self.buffer.is_empty() && !self.skipping {
+                        let line = mem::take(&mut self.buffer);
+                        return Some(Ok(Line::Complete(line)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Answers an NDJSON body of requests with one result line per request line,
+/// in order, streaming both ways so memory stays bounded by a single line.
+fn generate_lines(
+    mut ctx: Context,
+    options: RandomOptions,
+    distribution: Option<&'static str>,
+) -> Response<Body> {
+    let limit = body_limit(&ctx.state.config(), ctx.req.uri().path());
+    let lines = Lines {
+        body: mem::take(ctx.req.body_mut()),
+        buffer: Vec::new(),
+        limit,
+        skipping: false,
+        done: false,
+    };
+    let content_type = match options.format {
+        Format::Json => NDJSON,
+        format => format.content_type(),
+    };
+    let Context { state, remote, .. } = ctx;
+    let results = stream::unfold(
+        (lines, state, options),
+        move |(mut lines, state, options)| async move {
+            loop {
+                let res = match lines.next().await? {
+                    Err(err) => return Some((Err(err), (lines, state, options))),
+                    Ok(Line::Complete(ref line)) if line.iter().all(u8::is_ascii_whitespace) => {
+                        continue
+                    }
+                    Ok(Line::Complete(line)) => match distribution {
+                        Some(name) => parse_parameters(&line, name, &state.config()),
+                        None => parse_request(&line, &state.config()),
+                    }
+                    .and_then(|request| {
+                        state.check_distribution_limit(&remote, &request)?;
+                        generate(&request, &options, &state)
+                    }),
+                    Ok(Line::TooLong) => {
+                        Err(format_err!("line exceeds the limit of {} bytes", limit))
+                    }
+                };
+                let mut body = res.unwrap_or_else(|err| {
+                    serialize(options.format, &ErrorResponse::new(&err)).unwrap()
+                });
+                if !options.format.binary() {
+                    body.push(b'\n');
+                }
+                return Some((Ok(body), (lines, state, options)));
+            }
+        },
+    );
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::wrap_stream(results))
+        .unwrap()
+}
+
+/// `POST /quantile` and `POST /cdf`, evaluating `name` at the query's `param`.
+async fn evaluate(
+    mut ctx: Context,
+    name: &'static str,
+    param: &'static str,
+    evaluate: fn(&RngRequest, f64) -> Option<f64>,
+) -> Response<Body> {
+    let options =
+        ctx.options(false)
+            .and_then(|options| match query_param::<f64>(&ctx.query, param)? {
+                Some(p) if name == "quantile" && !(0.0..=1.0).contains(&p) => {
+                    bail!("p must be between 0 and 1")
+                }
+                Some(value) if value.is_nan() => bail!("{} must be a number", param),
+                Some(value) => Ok((options, value)),
+                None => bail!("missing {}", param),
+            });
+    let (options, at) = match options {
+        Ok(options) => options,
+        Err(err) => return ctx.reject(Rejection::Unprocessable, err.to_string()),
+    };
+    let body = match ctx.body(options.format).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    let state = &ctx.state;
+    let request = match parse_request(&body, &state.config()) {
+        Ok(request) => request,
+        Err(err) => return state.reject_error(&ctx.remote, &err, options.format),
+    };
+    let res = match evaluate(&request, at) {
+        Some(value) if value.is_finite() => options.serialize_single(&RngResponse {
+            value: Sample::Scalar(value),
+            kind: None,
+            uniform: None,
+            density: None,
+            generation_ns: None,
+        }),
+        Some(_) => Err(format_err!("{} at {}={} is unbounded", name, param, at)),
+        None => {
+            return state.reject(
+                &ctx.remote,
+                Rejection::NotImplemented,
+                format!("{} is not implemented for {}", name, request.name()),
+                options.format,
+            )
+        }
+    };
+    match res {
+        Ok(body) => respond(options.format, body),
+        Err(err) => state.reject(
+            &ctx.remote,
+            Rejection::Unprocessable,
+            err.to_string(),
+            options.format,
+        ),
+    }
+}
+
+async fn validate(mut ctx: Context) -> Response<Body> {
+    let body = match ctx.body(ctx.error_format).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    match parse_request(&body, &ctx.state.config()) {
+        Ok(request) => Response::builder()
+            .header(CONTENT_TYPE, Format::Json.content_type())
+            .body(serde_json::to_vec(&request).unwrap().into())
+            .unwrap(),
+        Err(err) => ctx.reject_error(&err),
+    }
+}
+
+async fn verify_sequence(mut ctx: Context) -> Response<Body> {
+    let body = match ctx.body(ctx.error_format).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    match verify(&body, &ctx.state) {
+        Ok(verified) => Response::builder()
+            .status(if verified.verified {
+                StatusCode::OK
+            } else {
+                StatusCode::CONFLICT
+            })
+            .header(CONTENT_TYPE, Format::Json.content_type())
+            .body(serde_json::to_vec(&verified).unwrap().into())
+            .unwrap(),
+        Err(err) => ctx.reject_error(&err),
+    }
+}
+
+/// `POST /sample`, `k` distinct items in random order, and `POST /shuffle`,
+/// a random permutation of all of them.
+async fn draw(mut ctx: Context, name: &'static str) -> Response<Body> {
+    let options = match ctx.options(false) {
+        Ok(options) => options,
+        Err(err) => return ctx.reject(Rejection::Unprocessable, err.to_string()),
+    };
+    let body = match ctx.body(options.format).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    let state = &ctx.state;
+    let res = serde_json::from_slice::<DrawRequest>(&body)
+        .map_err(Error::from)
+        .and_then(|request| {
+            let len = request.len(&state.config())?;
+            let k = match (name, request.k) {
+                ("shuffle", None) => len,
+                ("shuffle", Some(_)) => bail!("k is only accepted by /sample"),
+                (_, Some(k)) if k > len => bail!("k {} exceeds the {} items", k, len),
+                (_, Some(k)) => k,
+                (_, None) => bail!("missing k"),
+            };
+            let indices =
+                options.with_rng(state, k, |rng| rand::seq::index::sample(rng, len, k))?;
+            let drawn = request.items(indices.into_iter());
+            let request = serde_json::json!({"distribution": name, "parameters": request});
+            state.audit(&options.request_id, &request, &drawn);
+            options.serialize(&drawn)
+        });
+    match res {
+        Ok(body) => respond(options.format, body),
+        Err(err) => state.reject(
+            &ctx.remote,
+            Rejection::Unprocessable,
+            err.to_string(),
+            options.format,
+        ),
+    }
+}
+
+/// `POST /quasirandom`, points of a Sobol or Halton sequence.
+async fn quasi_random(mut ctx: Context) -> Response<Body> {
+    let options = match ctx.options(false) {
+        Ok(options) => options,
+        Err(err) => return ctx.reject(Rejection::Unprocessable, err.to_string()),
+    };
+    let body = match ctx.body(options.format).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    let state = &ctx.state;
+    let res = serde_json::from_slice::<QuasiRequest>(&body)
+        .map_err(Error::from)
+        .and_then(|request| {
+            let points = quasirandom(&request, state)?;
+            state.audit(&options.request_id, &request, &points.points);
+            options.serialize(&points)
+        });
+    match res {
+        Ok(body) => respond(options.format, body),
+        Err(err) => state.reject(
+            &ctx.remote,
+            Rejection::Unprocessable,
+            err.to_string(),
+            options.format,
+        ),
+    }
+}
+
+fn string(ctx: Context) -> Response<Body> {
+    let (state, query, config) = (&ctx.state, &ctx.query, &ctx.state.config());
+    let res = ctx.options(false).and_then(|options| {
+        let len = query_param(query, "len")?.unwrap_or(16);
+        if len > config.max_string_len {
+            bail!(
+                "len {} exceeds the maximum of {}",
+                len,
+                config.max_string_len
+            );
+        }
+        let charset = match query["charset"].as_str() {
+            Some("custom") => Charset::custom(query["chars"].as_str().unwrap_or(""))?,
+            _ => query_param(query, "charset")?.unwrap_or(Charset::Alphanumeric),
+        };
+        let mut values = state.with_rng(options.entropy_source, |rng| {
+            (0..options.count.unwrap_or(1))
+                .map(|_| charset.generate(rng, len))
+                .collect::<Vec<_>>()
+        })?;
+        let request = serde_json::json!({
+            "distribution": "string",
+            "parameters": {"len": len, "charset": query["charset"], "chars": query["chars"]},
+        });
+        state.audit(&options.request_id, &request, &values);
+        let body = match options.count {
+            Some(_) => options.serialize(&values)?,
+            None if options.envelope => options.serialize(&StringResponse {
+                value: values.swap_remove(0),
+            })?,
+            None => options.serialize(&values[0])?,
+        };
+        Ok((options.format, body))
+    });
+    match res {
+        Ok((format, body)) => respond(format, body),
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+/// `POST /password`, passwords meeting a `PasswordPolicy`, always from the
+/// operating system's generator.
+async fn password(mut ctx: Context) -> Response<Body> {
+    let mut options = match ctx.options(false) {
+        Ok(options) => options,
+        Err(err) => return ctx.reject(Rejection::Unprocessable, err.to_string()),
+    };
+    let body = match ctx.body(options.format).await {
+        Ok(body) => body,
+        Err(resp) => return resp,
+    };
+    let (state, config) = (&ctx.state, &ctx.state.config());
+    let res = serde_json::from_slice(&body)
+        .map_err(Error::from)
+        .and_then(|mut request| {
+            if let Some(query) = body_options(&mut request, &ctx.query)? {
+                options = RandomOptions::parse(&query, ctx.req.headers(), config, false)?;
+            }
+            let policy = serde_json::from_value::<PasswordPolicy>(request)?;
+            let alphabet = policy.alphabet(config)?;
+            let mut rng = OsRng::new()?;
+            let mut passwords = (0..options.count.unwrap_or(1))
+                .map(|_| policy.generate(&alphabet, &mut rng))
+                .collect::<Vec<_>>();
+            let request = serde_json::json!({"distribution": "password", "parameters": policy});
+            // Like `/bytes`, only how many passwords were made is audited.
+            state.audit(&options.request_id, &request, &passwords.len());
+            match options.count {
+                Some(_) => options.serialize(&passwords),
+                None if options.envelope => options.serialize(&StringResponse {
+                    value: passwords.swap_remove(0),
+                }),
+                None => options.serialize(&passwords[0]),
+            }
+        });
+    match res {
+        Ok(body) => respond(options.format, body),
+        Err(err) => state.reject(
+            &ctx.remote,
+            Rejection::Unprocessable,
+            err.to_string(),
+            options.format,
+        ),
+    }
+}
+
+/// `GET /bytes`, always from the operating system's generator since the
+/// bytes are meant for tokens and nonces.
+fn bytes(ctx: Context) -> Response<Body> {
+    let (state, query, config) = (&ctx.state, &ctx.query, &ctx.state.config());
+    let res = ctx.options(false).and_then(|options| {
+        let len = query_param(query, "len")?.unwrap_or(32);
+        if len == 0 || len > config.max_bytes_len {
+            bail!(
+                "len must be between 1 and {}, got {}",
+                config.max_bytes_len,
+                len
+            );
+        }
+        let encoding = query_param(query, "encoding")?.unwrap_or(ByteEncoding::Hex);
+        let mut bytes = vec![0; len];
+        OsRng::new()?.fill_bytes(&mut bytes);
+        let value = encoding.encode(&bytes);
+        let request = serde_json::json!({
+            "distribution": "bytes",
+            "parameters": {"len": len, "encoding": query["encoding"]},
+        });
+        // The bytes are secrets, so the audit log only records their length.
+        state.audit(&options.request_id, &request, &len);
+        let body = if options.envelope {
+            options.serialize(&StringResponse { value })?
+        } else {
+            options.serialize(&value)?
+        };
+        Ok((options.format, body))
+    });
+    match res {
+        Ok((format, body)) => respond(format, body),
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+/// `GET /uuid`, version 4 unless `?version=7`, and `GET /ulid`; `count`
+/// returns a batch.
+fn identifiers(ctx: Context, name: &'static str) -> Response<Body> {
+    let state = &ctx.state;
+    let res = ctx.options(false).and_then(|options| {
+        let (kind, parameters) = match name {
+            "uuid" => match query_param(&ctx.query, "version")?.unwrap_or(4) {
+                4 => (IdKind::UuidV4, serde_json::json!({"version": 4})),
+                7 => (IdKind::UuidV7, serde_json::json!({"version": 7})),
+                version => bail!("unsupported uuid version {}, expected 4 or 7", version),
+            },
+            _ => (IdKind::Ulid, serde_json::json!({})),
+        };
+        let request = serde_json::json!({"distribution": name, "parameters": parameters});
+        let mut ids = state.with_rng(options.entropy_source, |rng| {
+            (0..options.count.unwrap_or(1))
+                .map(|_| kind.generate(rng))
+                .collect::<Vec<_>>()
+        })?;
+        state.audit(&options.request_id, &request, &ids);
+        let body = match options.count {
+            Some(_) => options.serialize(&ids)?,
+            None if options.envelope => options.serialize(&StringResponse {
+                value: ids.swap_remove(0),
+            })?,
+            None => options.serialize(&ids[0])?,
+        };
+        Ok((options.format, body))
+    });
+    match res {
+        Ok((format, body)) => respond(format, body),
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+/// `GET /roll?dice=3d6+2`, each die's roll and the total; `count` rolls the
+/// expression that many times.
+fn roll(ctx: Context) -> Response<Body> {
+    let state = &ctx.state;
+    let res = ctx.options(false).and_then(|options| {
+        // An unescaped `+` in a query string arrives as a space.
+        let notation = ctx.query["dice"]
+            .as_str()
+            .map(|dice| dice.replace(' ', "+"));
+        let dice = match notation {
+            Some(ref notation) => notation
+                .parse::<dice::Dice>()
+                .map_err(|err| format_err!("invalid dice {:?}: {}", notation, err))?,
+            None => bail!("missing dice"),
+        };
+        let request = serde_json::json!({
+            "distribution": "dice",
+            "parameters": {"dice": notation},
+        });
+        let mut rolls = state.with_rng(options.entropy_source, |rng| {
+            (0..options.count.unwrap_or(1))
+                .map(|_| dice.roll(rng))
+                .collect::<Vec<_>>()
+        })?;
+        state.audit(&options.request_id, &request, &rolls);
+        let body = match options.count {
+            Some(_) => options.serialize(&rolls)?,
+            None => options.serialize(&rolls.swap_remove(0))?,
+        };
+        Ok((options.format, body))
+    });
+    match res {
+        Ok((format, body)) => respond(format, body),
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+fn flip(ctx: Context) -> Response<Body> {
+    let state = &ctx.state;
+    let res = ctx.options(false).and_then(|options| {
+        let p = query_param(&ctx.query, "p")?.unwrap_or_else(default_p);
+        let mut request = RngRequest::Bernoulli { p };
+        request.enforce(&state.config())?;
+        let value = state
+            .with_rng(options.entropy_source, |rng| sample(&request, rng))??
+            .scalar()
+            == Some(1.0);
+        state.audit(&options.request_id, &request, &value);
+        let body = if options.envelope {
+            options.serialize(&FlipResponse { value })?
+        } else {
+            options.serialize(&value)?
+        };
+        Ok((options.format, body))
+    });
+    match res {
+        Ok((format, body)) => respond(format, body),
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+fn seed(ctx: Context) -> Response<Body> {
+    let state = &ctx.state;
+    let res = ctx.options(false).and_then(|options| {
+        let mut rng = OsRng::new()?;
+        let request = serde_json::json!({"distribution": "seed"});
+        let body = match options.count {
+            Some(count) => {
+                let seeds = (0..count).map(|_| rng.gen()).collect::<Vec<u64>>();
+                state.audit(&options.request_id, &request, &seeds);
+                options.serialize(&seeds)?
+            }
+            None => {
+                let seed = rng.gen::<u64>();
+                state.audit(&options.request_id, &request, &seed);
+                if options.envelope {
+                    options.serialize(&SeedResponse { seed })?
+                } else {
+                    options.serialize(&seed)?
+                }
+            }
+        };
+        Ok((options.format, body))
+    });
+    match res {
+        Ok((format, body)) => respond(format, body),
+        Err(err) => ctx.reject(Rejection::Unprocessable, err.to_string()),
+    }
+}
+
+fn health() -> Response<Body> {
+    let body = serde_json::to_vec(&StatusResponse { status: "ok" }).unwrap();
+    Response::builder()
+        .header(CONTENT_TYPE, Format::Json.content_type())
+        .body(body.into())
+        .unwrap()
+}
+
+/// `GET /ready` and `/readyz`: ready once the listener is bound, and not
+/// while the load monitor reports the host overloaded.
+fn ready(ctx: Context) -> Response<Body> {
+    let checks = ReadyChecks {
+        listener: if ctx.state.ready.load(Ordering::SeqCst) {
+            "ok"
+        } else {
+            "pending"
+        },
+        load: match ctx.state.check_load() {
+            Ok(()) => "ok",
+            Err(_) => "overloaded",
+        },
+    };
+    let (code, status) = match (checks.listener, checks.load) {
+        ("ok", "ok") => (StatusCode::OK, "ready"),
+        ("ok", _) => (StatusCode::SERVICE_UNAVAILABLE, "overloaded"),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, "starting"),
+    };
+    Response::builder()
+        .status(code)
+        .header(CONTENT_TYPE, Format::Json.content_type())
+        .body(
+            serde_json::to_vec(&ReadyResponse { status, checks })
+                .unwrap()
+                .into(),
+        )
+        .unwrap()
+}
+
+fn stats(ctx: Context) -> Response<Body> {
+    let rejections = Rejection::ALL
+        .iter()
+        .map(|rejection| {
+            let counter = ctx
+                .state
+                .metrics
+                .rejections
+                .with_label_values(&[rejection.reason()]);
+            (rejection.reason(), counter.get())
+        })
+        .collect();
+    let body = serde_json::to_vec(&StatsResponse {
+        rejections: &rejections,
+    })
+    .unwrap();
+    Response::builder()
+        .header(CONTENT_TYPE, Format::Json.content_type())
+        .body(body.into())
+        .unwrap()
+}
+
+fn version(ctx: Context) -> Response<Body> {
+    let body = serde_json::to_vec(&VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+    })
+    .unwrap();
+    respond_cached(ctx.req.headers(), body)
+}
+
+fn distributions(ctx: Context) -> Response<Body> {
+    let distributions = RngRequest::defaults()
+        .iter()
+        .map(RngRequest::describe)
+        .collect();
+    let body = serde_json::to_vec(&DistributionsResponse { distributions }).unwrap();
+    respond_cached(ctx.req.headers(), body)
+}
+
+fn openapi(ctx: Context) -> Response<Body> {
+    let body = serde_json::to_vec(&openapi::document()).unwrap();
+    respond_cached(ctx.req.headers(), body)
+}
+
+fn docs() -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(openapi::DOCS_PAGE.into())
+        .unwrap()
+}
+
+fn warmup(ctx: Context) -> Response<Body> {
+    match ctx.state.warm_up() {
+        Ok(warmed) => {
+            let body = serde_json::to_vec(&WarmupResponse { warmed }).unwrap();
+            Response::builder()
+                .header(CONTENT_TYPE, Format::Json.content_type())
+                .body(body.into())
+                .unwrap()
+        }
+        Err(err) => ctx.reject_error(&err),
+    }
+}
+
+fn metrics(ctx: Context) -> Response<Body> {
+    match ctx.state.metrics() {
+        Ok(metrics) => Response::builder()
+            .header(CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(metrics.into()),
+        Err(err) => {
+            warn!("Cannot encode metrics: {}", err);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+        }
+    }
+    .unwrap()
+}
+
+fn streams(ctx: Context) -> Response<Body> {
+    let mut streams = ctx
+        .state
+        .streams
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(name, stream)| StreamInfo {
+            name: name.clone(),
+            draws: stream.lock().unwrap().draws,
+        })
+        .collect::<Vec<_>>();
+    streams.sort_by(|a, b| a.name.cmp(&b.name));
+    let body = serde_json::to_vec(&StreamsResponse { streams }).unwrap();
+    Response::builder()
+        .header(CONTENT_TYPE, Format::Json.content_type())
+        .body(body.into())
+        .unwrap()
+}
+
+fn reset_stream(ctx: Context, name: &str) -> Response<Body> {
+    match ctx.state.streams.read().unwrap().get(name) {
+        Some(stream) => {
+            stream.lock().unwrap().reset();
+            let body = serde_json::to_vec(&StreamInfo {
+                name: name.to_owned(),
+                draws: 0,
+            })
+            .unwrap();
+            Response::builder()
+                .header(CONTENT_TYPE, Format::Json.content_type())
+                .body(body.into())
+                .unwrap()
+        }
+        None => ctx.reject(Rejection::NotFound, format!("unknown stream {:?}", name)),
+    }
+}
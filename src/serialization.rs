@@ -0,0 +1,486 @@
+//! The response formats and how each one writes a response body.
+
+use super::*;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Json,
+    Text,
+    Csv,
+    Cbor,
+    MessagePack,
+}
+
+impl Format {
+    pub(crate) fn content_type(self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Text => "text/plain; charset=utf-8",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Cbor => "application/cbor",
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// Binary bodies are self-delimiting, so streams of them are concatenated
+    /// without the newline text formats end each record with.
+    pub(crate) fn binary(self) -> bool {
+        match self {
+            Format::Json | Format::Text | Format::Csv => false,
+            Format::Cbor | Format::MessagePack => true,
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" | "application/*" | "*/*" => Some(Format::Json),
+            "text/plain" | "text/*" => Some(Format::Text),
+            "text/csv" => Some(Format::Csv),
+            "application/cbor" => Some(Format::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Format::MessagePack)
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn negotiate(query: &Value, headers: &HeaderMap) -> Result<Self, Error> {
+        if let Some(format) = query["format"].as_str() {
+            return format.parse();
+        }
+        let accept = headers
+            .get(ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .unwrap_or("");
+        let mut ranges = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let format = Format::from_media_type(parts.next()?)?;
+                let quality = parts
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((format, quality))
+            })
+            .filter(|&(_, quality)| quality > 0.0)
+            .collect::<Vec<_>>();
+        ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        Ok(ranges.first().map_or(Format::Json, |&(format, _)| format))
+    }
+}
+
+impl Format {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Text => "text",
+            Format::Csv => "csv",
+            Format::Cbor => "cbor",
+            Format::MessagePack => "msgpack",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(format: &str) -> Result<Self, Error> {
+        match format {
+            "json" => Ok(Format::Json),
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "cbor" => Ok(Format::Cbor),
+            "msgpack" => Ok(Format::MessagePack),
+            _ => Err(format_err!("unsupported format {}", format)),
+        }
+    }
+}
+
+pub trait Text: Serialize {
+    fn text(&self) -> String;
+
+    /// One row per element of a top-level array, with nested arrays and
+    /// objects flattened into `name_0`, `name_1`, ... columns.
+    fn csv(&self) -> Result<String, Error> {
+        let rows = match serde_json::to_value(self)? {
+            Value::Array(rows) => rows,
+            row => vec![row],
+        };
+        let mut header = Vec::new();
+        let mut lines = Vec::new();
+        for row in &rows {
+            let mut names = Vec::new();
+            let mut cells = Vec::new();
+            csv_columns("", row, &mut names, &mut cells);
+            if header.is_empty() {
+                header = names;
+            }
+            lines.push(cells.join(","));
+        }
+        if header.is_empty() {
+            header.push("value".into());
+        }
+        lines.insert(0, header.join(","));
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+fn csv_columns(name: &str, value: &Value, names: &mut Vec<String>, cells: &mut Vec<String>) {
+    let column = |key: &dyn Display| {
+        if name.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}_{}", name, key)
+        }
+    };
+    match value {
+        Value::Array(items) => {
+            let name = if name.is_empty() { "value" } else { name };
+            for (i, item) in items.iter().enumerate() {
+                csv_columns(&format!("{}_{}", name, i), item, names, cells);
+            }
+        }
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                csv_columns(&column(key), field, names, cells);
+            }
+        }
+        scalar => {
+            names.push(if name.is_empty() {
+                "value".into()
+            } else {
+                name.into()
+            });
+            cells.push(csv_cell(scalar));
+        }
+    }
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) if text.contains(&[',', '"', '\n', '\r'][..]) => {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        }
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+impl Text for f64 {
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Text for Sample {
+    fn text(&self) -> String {
+        match self {
+            Sample::Scalar(value) => value.text(),
+            Sample::Vector(values) => values
+                .iter()
+                .map(f64::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+            Sample::Text(text) => text.clone(),
+        }
+    }
+}
+
+impl Text for u64 {
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Text for SeedResponse {
+    fn text(&self) -> String {
+        self.seed.text()
+    }
+}
+
+impl<T: Text> Text for Vec<T> {
+    fn text(&self) -> String {
+        self.iter().map(Text::text).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl Text for RngResponse {
+    fn text(&self) -> String {
+        self.value.text()
+    }
+}
+
+impl Text for HistogramResponse {
+    fn text(&self) -> String {
+        self.edges
+            .windows(2)
+            .zip(&self.counts)
+            .map(|(edges, count)| format!("{}\t{}\t{}", edges[0], edges[1], count))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn csv(&self) -> Result<String, Error> {
+        let mut csv = String::from("start,end,count\n");
+        for (edges, count) in self.edges.windows(2).zip(&self.counts) {
+            csv += &format!("{},{},{}\n", edges[0], edges[1], count);
+        }
+        Ok(csv)
+    }
+}
+
+impl Text for SummaryResponse {
+    fn text(&self) -> String {
+        format!(
+            "count\t{}\nmean\t{}\nvariance\t{}\nmin\t{}\nmax\t{}\n{}",
+            self.count,
+            self.mean,
+            self.variance,
+            self.min,
+            self.max,
+            self.histogram.text()
+        )
+    }
+
+    fn csv(&self) -> Result<String, Error> {
+        bail!("stats has no csv form, use json or text")
+    }
+}
+
+impl Text for String {
+    fn text(&self) -> String {
+        self.clone()
+    }
+}
+
+/// Strings as themselves and anything else as its JSON.
+impl Text for Value {
+    fn text(&self) -> String {
+        match *self {
+            Value::String(ref text) => text.clone(),
+            ref value => value.to_string(),
+        }
+    }
+}
+
+impl Text for QuasiResponse {
+    fn text(&self) -> String {
+        self.points
+            .iter()
+            .map(|point| {
+                let coordinates = point.iter().map(f64::to_string).collect::<Vec<_>>();
+                coordinates.join("\t")
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Text for dice::Roll {
+    fn text(&self) -> String {
+        self.total.to_string()
+    }
+}
+
+impl Text for StringResponse {
+    fn text(&self) -> String {
+        self.value.clone()
+    }
+}
+
+impl Text for bool {
+    fn text(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Text for FlipResponse {
+    fn text(&self) -> String {
+        self.value.text()
+    }
+}
+
+impl Text for ErrorResponse {
+    fn text(&self) -> String {
+        self.error.message.clone()
+    }
+}
+
+/// Wraps another formatter, keeping its layout but always writing floats
+/// with a decimal point.
+pub(crate) struct FixedPointFormatter<F>(pub(crate) F);
+
+impl<F: Formatter> Formatter for FixedPointFormatter<F> {
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.begin_array(writer)
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_array(writer)
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.0.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.0.begin_object_key(writer, first)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.0.end_object_value(writer)
+    }
+
+    fn write_f64<W: ?Sized + Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        let value = value.to_string();
+        writer.write_all(value.as_bytes())?;
+        if !value.contains('.') {
+            writer.write_all(b".0")?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn to_json_with<T: Serialize, F: Formatter>(
+    resp: &T,
+    formatter: F,
+) -> Result<Vec<u8>, Error> {
+    let mut buffer = Vec::new();
+    resp.serialize(&mut serde_json::Serializer::with_formatter(
+        &mut buffer,
+        formatter,
+    ))?;
+    Ok(buffer)
+}
+
+pub fn serialize<T: Text>(format: Format, resp: &T) -> Result<Vec<u8>, Error> {
+    match format {
+        Format::Json => Ok(serde_json::to_vec(resp)?),
+        Format::Text => Ok(resp.text().into_bytes()),
+        Format::Csv => Ok(resp.csv()?.into_bytes()),
+        Format::Cbor => Ok(serde_cbor::to_vec(resp)?),
+        Format::MessagePack => Ok(rmp_serde::to_vec_named(resp)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Sample> {
+        vec![
+            Sample::Scalar(1.5),
+            Sample::Vector(vec![0.25, 2.0]),
+            Sample::Text("a,\"b\"".to_owned()),
+        ]
+    }
+
+    #[test]
+    fn json() {
+        let body = serialize(Format::Json, &samples()).unwrap();
+        assert_eq!(body, br#"[1.5,[0.25,2.0],"a,\"b\""]"#);
+    }
+
+    #[test]
+    fn text() {
+        let body = serialize(Format::Text, &samples()).unwrap();
+        assert_eq!(String::from_utf8(body).unwrap(), "1.5\n0.25,2\na,\"b\"");
+    }
+
+    #[test]
+    fn csv() {
+        let rows = vec![
+            Sample::Vector(vec![1.0, 2.5]),
+            Sample::Vector(vec![3.0, 4.0]),
+        ];
+        let body = serialize(Format::Csv, &rows).unwrap();
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "value_0,value_1\n1.0,2.5\n3.0,4.0\n"
+        );
+        let body = serialize(Format::Csv, &vec![Sample::Text("a,\"b\"".to_owned())]).unwrap();
+        assert_eq!(String::from_utf8(body).unwrap(), "value\n\"a,\"\"b\"\"\"\n");
+    }
+
+    #[test]
+    fn cbor() {
+        let body = serialize(Format::Cbor, &samples()).unwrap();
+        let decoded = serde_cbor::from_slice::<Value>(&body).unwrap();
+        assert_eq!(decoded, serde_json::to_value(samples()).unwrap());
+    }
+
+    #[test]
+    fn message_pack() {
+        let body = serialize(Format::MessagePack, &samples()).unwrap();
+        let decoded = rmp_serde::from_slice::<Value>(&body).unwrap();
+        assert_eq!(decoded, serde_json::to_value(samples()).unwrap());
+    }
+
+    #[test]
+    fn fixed_point_json_keeps_a_decimal_point() {
+        let values = vec![1e16, 0.5];
+        assert_eq!(serde_json::to_vec(&values).unwrap(), b"[1e+16,0.5]");
+        let body = to_json_with(&values, FixedPointFormatter(CompactFormatter)).unwrap();
+        assert_eq!(body, b"[10000000000000000.0,0.5]");
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for &format in &[
+            Format::Json,
+            Format::Text,
+            Format::Csv,
+            Format::Cbor,
+            Format::MessagePack,
+        ] {
+            assert_eq!(
+                format.name().parse::<Format>().unwrap().name(),
+                format.name()
+            );
+        }
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn negotiate_prefers_the_query_then_the_best_accepted_type() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("text/csv;q=0.5, application/cbor, application/json;q=0"),
+        );
+        let negotiate = |query| Format::negotiate(&query, &headers).unwrap().name();
+        assert_eq!(negotiate(Value::Null), "cbor");
+        assert_eq!(negotiate(serde_json::json!({"format": "text"})), "text");
+        let empty = HeaderMap::new();
+        assert_eq!(
+            Format::negotiate(&Value::Null, &empty).unwrap().name(),
+            "json"
+        );
+    }
+}
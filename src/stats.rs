@@ -0,0 +1,195 @@
+use std::f64::consts::{PI, SQRT_2};
+
+fn polynomial(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().rev().fold(0.0, |acc, c| acc * x + c)
+}
+
+/// Inverse of the standard normal CDF (Wichura's AS241, ~1e-16 accuracy).
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 8] = [
+        3.387_132_872_796_366_6,
+        133.141_667_891_784_38,
+        1_971.590_950_306_551_4,
+        13_731.693_765_509_46,
+        45_921.953_931_549_87,
+        67_265.770_927_008_7,
+        33_430.575_583_588_13,
+        2_509.080_928_730_122_7,
+    ];
+    const B: [f64; 8] = [
+        1.0,
+        42.313_330_701_600_91,
+        687.187_007_492_057_9,
+        5_394.196_021_424_751,
+        21_213.794_301_586_597,
+        39_307.895_800_092_71,
+        28_729.085_735_721_943,
+        5_226.495_278_852_546,
+    ];
+    const C: [f64; 8] = [
+        1.423_437_110_749_683_5,
+        4.630_337_846_156_545,
+        5.769_497_221_460_691,
+        3.647_848_324_763_204_6,
+        1.270_458_252_452_368_4,
+        0.241_780_725_177_450_6,
+        0.022_723_844_989_269_184,
+        7.745_450_142_783_414e-4,
+    ];
+    const D: [f64; 8] = [
+        1.0,
+        2.053_191_626_637_759,
+        1.676_384_830_183_803_8,
+        0.689_767_334_985_1,
+        0.148_103_976_427_480_08,
+        0.015_198_666_563_616_457,
+        5.475_938_084_995_345e-4,
+        1.050_750_071_644_416_8e-9,
+    ];
+    const E: [f64; 8] = [
+        6.657_904_643_501_103,
+        5.463_784_911_164_114,
+        1.784_826_539_917_291_3,
+        0.296_560_571_828_504_9,
+        0.026_532_189_526_576_124,
+        0.001_242_660_947_388_078_4,
+        2.711_555_568_743_487_6e-5,
+        2.010_334_399_292_288e-7,
+    ];
+    const F: [f64; 8] = [
+        1.0,
+        0.599_832_206_555_887_9,
+        0.136_929_880_922_735_8,
+        0.014_875_361_290_850_615,
+        7.868_691_311_456_133e-4,
+        1.846_318_317_510_054_8e-5,
+        1.421_511_758_316_446e-7,
+        2.044_263_103_389_939_7e-15,
+    ];
+
+    let q = p - 0.5;
+    if q.abs() <= 0.425 {
+        let r = 0.180_625 - q * q;
+        return q * polynomial(&A, r) / polynomial(&B, r);
+    }
+    let r = if q < 0.0 { p } else { 1.0 - p };
+    if r <= 0.0 {
+        return if q < 0.0 {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        };
+    }
+    let r = (-r.ln()).sqrt();
+    let value = if r <= 5.0 {
+        let r = r - 1.6;
+        polynomial(&C, r) / polynomial(&D, r)
+    } else {
+        let r = r - 5.0;
+        polynomial(&E, r) / polynomial(&F, r)
+    };
+    if q < 0.0 {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Complementary error function, accurate to ~1e-15 across the real line.
+pub fn erfc(x: f64) -> f64 {
+    if x < 0.0 {
+        return 2.0 - erfc(-x);
+    }
+    if x <= 3.0 {
+        // erf(x) = 2/sqrt(pi) * exp(-x^2) * sum(2^n x^(2n+1) / (2n+1)!!)
+        let mut term = x;
+        let mut sum = x;
+        let mut n = 0.0;
+        while term > sum * 1e-17 {
+            n += 1.0;
+            term *= 2.0 * x * x / (2.0 * n + 1.0);
+            sum += term;
+        }
+        return 1.0 - 2.0 / PI.sqrt() * (-x * x).exp() * sum;
+    }
+    // Continued fraction erfc(x) = exp(-x^2)/sqrt(pi) / (x + (1/2)/(x + 1/(x + (3/2)/(x + ...))))
+    let mut fraction = x;
+    for k in (1..60).rev() {
+        fraction = x + f64::from(k) / 2.0 / fraction;
+    }
+    (-x * x).exp() / PI.sqrt() / fraction
+}
+
+pub fn normal_cdf(z: f64) -> f64 {
+    0.5 * erfc(-z / SQRT_2)
+}
+
+/// Lower-triangular `L` with `L * L^T = matrix` for a symmetric positive
+/// semi-definite matrix, or `None` when the matrix is neither.
+pub fn cholesky(matrix: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let tolerance = |value: f64| 1e-10 * value.abs().max(1.0);
+    for (i, row) in matrix.iter().enumerate() {
+        for (j, &value) in row.iter().enumerate().take(i) {
+            if (value - matrix[j][i]).abs() > tolerance(value) {
+                return None;
+            }
+        }
+    }
+    let mut lower = vec![vec![0.0; n]; n];
+    for j in 0..n {
+        let dot =
+            |lower: &[Vec<f64>], i: usize| (0..j).map(|k| lower[i][k] * lower[j][k]).sum::<f64>();
+        let pivot = matrix[j][j] - dot(&lower, j);
+        if pivot < -tolerance(matrix[j][j]) {
+            return None;
+        }
+        if pivot <= tolerance(matrix[j][j]) {
+            // A zero pivot leaves the column zero, which is only consistent
+            // when the rest of the column has no variance left to explain.
+            for (i, row) in matrix.iter().enumerate().skip(j + 1) {
+                if (row[j] - dot(&lower, i)).abs() > tolerance(row[j]) {
+                    return None;
+                }
+            }
+            continue;
+        }
+        lower[j][j] = pivot.sqrt();
+        for i in j + 1..n {
+            lower[i][j] = (matrix[i][j] - dot(&lower, i)) / lower[j][j];
+        }
+    }
+    Some(lower)
+}
+
+pub fn normal_pdf(z: f64) -> f64 {
+    (-z * z / 2.0).exp() / (2.0 * PI).sqrt()
+}
+
+/// Natural log of the gamma function for positive `x` (Lanczos, g = 7).
+pub fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection keeps the series in the range where it converges.
+        return (PI / (PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| {
+            sum + c / (x + i as f64 + 1.0)
+        });
+    0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
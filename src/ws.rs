@@ -0,0 +1,228 @@
+//! The WebSocket protocol (RFC 6455) as far as `GET /ws` needs it: the
+//! handshake's accept key and a session that hands each complete text or
+//! binary message to a callback and sends back what it returns.
+
+use super::*;
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+const PROTOCOL_ERROR: u16 = 1002;
+const TOO_BIG: u16 = 1009;
+
+/// The `Sec-WebSocket-Accept` answering a client's `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    BASE64.encode(Sha1::digest(format!("{}{}", key.trim(), ACCEPT_GUID)))
+}
+
+/// Whether `headers` ask to upgrade to a version 13 WebSocket.
+pub(crate) fn is_upgrade(headers: &HeaderMap) -> bool {
+    let has = |name: &str, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    has("connection", "upgrade")
+        && has("upgrade", "websocket")
+        && has("sec-websocket-version", "13")
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(
+    io: &mut S,
+    opcode: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    io.write_all(&frame).await?;
+    io.flush().await
+}
+
+async fn close<S: AsyncWrite + Unpin>(io: &mut S, code: u16, reason: &str) -> io::Result<()> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason.as_bytes());
+    write_frame(io, CLOSE, &payload).await
+}
+
+/// A reply to send back: its bytes and whether they go in a binary frame.
+pub(crate) type Reply = (bool, Vec<u8>);
+
+/// Serves messages until the client closes the connection, failing the
+/// session on protocol errors and messages over `limit` bytes.
+pub(crate) async fn session<S>(
+    mut io: S,
+    limit: usize,
+    mut on_message: impl FnMut(&[u8]) -> Reply,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut message = Vec::new();
+    let mut fragmented = false;
+    loop {
+        let mut header = [0; 2];
+        io.read_exact(&mut header).await?;
+        let (fin, opcode) = (header[0] & 0x80 != 0, header[0] & 0x0f);
+        if header[0] & 0x70 != 0 || header[1] & 0x80 == 0 {
+            return close(
+                &mut io,
+                PROTOCOL_ERROR,
+                "expected a masked frame without extensions",
+            )
+            .await;
+        }
+        let len = match header[1] & 0x7f {
+            126 => u64::from(io.read_u16().await?),
+            127 => io.read_u64().await?,
+            len => u64::from(len),
+        };
+        let control = opcode & 0x8 != 0;
+        if control && (!fin || len > 125) {
+            return close(&mut io, PROTOCOL_ERROR, "fragmented or long control frame").await;
+        }
+        let buffered = if fragmented { message.len() as u64 } else { 0 };
+        if !control && buffered + len > limit as u64 {
+            return close(&mut io, TOO_BIG, "message too large").await;
+        }
+        let mut mask = [0; 4];
+        io.read_exact(&mut mask).await?;
+        let mut payload = vec![0; len as usize];
+        io.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        match opcode {
+            CLOSE => {
+                // Echo the client's status code, as the closing handshake asks.
+                let code = payload.get(..2).unwrap_or(&[]);
+                return write_frame(&mut io, CLOSE, code).await;
+            }
+            PING => write_frame(&mut io, PONG, &payload).await?,
+            PONG => {}
+            TEXT | BINARY if !fragmented => message = payload,
+            CONTINUATION if fragmented => message.extend_from_slice(&payload),
+            _ => return close(&mut io, PROTOCOL_ERROR, "unexpected frame").await,
+        }
+        if control {
+            continue;
+        }
+        fragmented = !fin;
+        if fin {
+            let (binary, reply) = on_message(&message);
+            write_frame(&mut io, if binary { BINARY } else { TEXT }, &reply).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Writes a client frame, which is always masked.
+    async fn send(io: &mut DuplexStream, fin: bool, opcode: u8, payload: &[u8]) {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![
+            if fin { 0x80 } else { 0 } | opcode,
+            0x80 | payload.len() as u8,
+        ];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        io.write_all(&frame).await.unwrap();
+    }
+
+    /// Reads a short server frame as its opcode and payload.
+    async fn receive(io: &mut DuplexStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        io.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0] & 0xf0, 0x80, "unfragmented and unextended");
+        assert!(header[1] < 126, "unmasked and short");
+        let mut payload = vec![0; usize::from(header[1])];
+        io.read_exact(&mut payload).await.unwrap();
+        (header[0] & 0x0f, payload)
+    }
+
+    /// Runs a session that answers each message with its reverse.
+    fn echo(limit: usize) -> (DuplexStream, tokio::task::JoinHandle<io::Result<()>>) {
+        let (client, server) = duplex(1024);
+        let session = tokio::spawn(session(server, limit, |message| {
+            (false, message.iter().rev().cloned().collect())
+        }));
+        (client, session)
+    }
+
+    #[test]
+    fn accept_key_matches_the_rfc_sample() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn fragments_are_joined_around_control_frames() {
+        let (mut client, session) = echo(16);
+        send(&mut client, false, TEXT, b"abc").await;
+        send(&mut client, true, PING, b"ping").await;
+        send(&mut client, false, CONTINUATION, b"de").await;
+        send(&mut client, true, CONTINUATION, b"f").await;
+        assert_eq!(receive(&mut client).await, (PONG, b"ping".to_vec()));
+        assert_eq!(receive(&mut client).await, (TEXT, b"fedcba".to_vec()));
+        send(&mut client, true, CLOSE, &1000u16.to_be_bytes()).await;
+        assert_eq!(
+            receive(&mut client).await,
+            (CLOSE, 1000u16.to_be_bytes().to_vec())
+        );
+        session.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversize_messages_close_the_session() {
+        let (mut client, session) = echo(4);
+        send(&mut client, true, BINARY, b"abcd").await;
+        assert_eq!(receive(&mut client).await, (TEXT, b"dcba".to_vec()));
+        send(&mut client, false, TEXT, b"abc").await;
+        send(&mut client, true, CONTINUATION, b"de").await;
+        let (opcode, payload) = receive(&mut client).await;
+        assert_eq!(opcode, CLOSE);
+        assert_eq!(payload[..2], TOO_BIG.to_be_bytes());
+        session.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn fragmented_control_frames_are_protocol_errors() {
+        let (mut client, session) = echo(16);
+        send(&mut client, false, PING, b"ping").await;
+        let (opcode, payload) = receive(&mut client).await;
+        assert_eq!(opcode, CLOSE);
+        assert_eq!(payload[..2], PROTOCOL_ERROR.to_be_bytes());
+        session.await.unwrap().unwrap();
+    }
+}